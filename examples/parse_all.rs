@@ -24,18 +24,21 @@ fn walk_dir(dir: &Path) -> usize {
         } else {
             let file_name = path.file_name().unwrap().to_str().unwrap();
             if file_name == "Android.bp" {
-                let result = BluePrint::from_file(&path);
+                let result = BluePrint::from_file_recovering(&path);
                 match result {
-                    Ok(blueprint) => {
+                    Ok((blueprint, errors)) => {
                         num_files += 1;
                         println!("{}", path.to_string_lossy());
+                        for e in errors {
+                            println!("{}", e);
+                            println!("please report if this file is valid for soong!");
+                        }
                         for module in blueprint.modules {
                             println!("{} {:?}", module.typ, module.get("name"));
                         }
                     }
                     Err(e) => {
-                        println!("{}: {}", path.to_string_lossy(), e);
-                        panic!("please report! this file is not parsed correctly");
+                        println!("{}", e);
                     }
                 }
             }
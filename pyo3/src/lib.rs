@@ -1,8 +1,12 @@
+// pyo3 0.20 macros generate impl blocks that newer rustc flags as non-local
+#![allow(non_local_definitions)]
 use ::android_bp::Module as RsModule;
 use ::android_bp::BluePrint as RsBluePrint;
 use ::android_bp::Map as RsMap;
+use ::android_bp::Select as RsSelect;
+use ::android_bp::SelectPattern as RsSelectPattern;
 use ::android_bp::Value as RsValue;
-use std::collections::HashMap;
+use ::android_bp::IndexMap;
 
 use pyo3::prelude::*;
 
@@ -12,11 +16,11 @@ pub struct Module {
     #[pyo3(get, name="__type__")]
     pub typ: String,
     #[pyo3(get, name="__dict__")]
-    pub entries: HashMap<String, Value>,
+    pub entries: IndexMap<String, Value>,
 }
 impl From<&RsModule> for Module {
     fn from(module: &RsModule) -> Self {
-        let entries = module.entries.iter().map(value_to_pyvalue).collect();
+        let entries = module.entries().iter().map(value_to_pyvalue).collect();
         Module {
             typ: module.typ.to_owned(),
             entries,
@@ -33,7 +37,7 @@ impl Module {
     }
 }
 
-fn map_to_py(dict: &RsMap) -> HashMap<String, Value> {
+fn map_to_py(dict: &RsMap) -> IndexMap<String, Value> {
     dict.iter().map(value_to_pyvalue).collect()
 }
 #[derive(Debug, Clone)]
@@ -44,16 +48,72 @@ pub struct Function {
     #[pyo3(get)]
     pub args: Vec<Value>,
 }
+#[derive(Debug, Clone)]
+#[pyclass(unsendable)]
+pub struct Condition {
+    #[pyo3(get)]
+    pub name: String,
+    #[pyo3(get)]
+    pub args: Vec<String>,
+}
+#[derive(Debug, Clone)]
+#[pyclass(unsendable)]
+pub struct SelectCase {
+    /// patterns, as written: `default`, `any` and `any @ binding` are identifiers
+    #[pyo3(get)]
+    pub patterns: Vec<Value>,
+    /// None for `unset`
+    #[pyo3(get)]
+    pub value: Option<Value>,
+}
+#[derive(Debug, Clone)]
+#[pyclass(unsendable)]
+pub struct Select {
+    #[pyo3(get)]
+    pub conditions: Vec<Condition>,
+    #[pyo3(get)]
+    pub cases: Vec<SelectCase>,
+}
+impl From<&RsSelect> for Select {
+    fn from(select: &RsSelect) -> Self {
+        let pattern = |p: &RsSelectPattern| match p {
+            RsSelectPattern::String(s) => Value::String(s.to_owned()),
+            RsSelectPattern::Boolean(b) => Value::Boolean(*b),
+            RsSelectPattern::Default => Value::Ident("default".to_string()),
+            RsSelectPattern::Any(None) => Value::Ident("any".to_string()),
+            RsSelectPattern::Any(Some(b)) => Value::Ident(format!("any @ {}", b)),
+        };
+        Select {
+            conditions: select
+                .conditions
+                .iter()
+                .map(|c| Condition {
+                    name: c.name.to_owned(),
+                    args: c.args.to_owned(),
+                })
+                .collect(),
+            cases: select
+                .cases
+                .iter()
+                .map(|c| SelectCase {
+                    patterns: c.patterns.iter().map(pattern).collect(),
+                    value: c.value.as_ref().map(Value::from),
+                })
+                .collect(),
+        }
+    }
+}
 #[derive(Debug, Clone, FromPyObject)]
 pub enum Value {
     String(String),
     Array(Vec<Value>),
     Boolean(bool),
-    Map(HashMap<String, Value>),
+    Map(IndexMap<String, Value>),
     Ident(String),
     Integer(i64),
     ConcatExpr(Vec<Value>),
-    Function(Function)
+    Function(Function),
+    Select(Select),
 }
 impl IntoPy<Py<PyAny>> for Value {
     fn into_py(self, py: Python) -> Py<PyAny> {
@@ -66,6 +126,7 @@ impl IntoPy<Py<PyAny>> for Value {
             Value::Integer(i) => i.into_py(py),
             Value::ConcatExpr(c) => c.into_py(py),
             Value::Function(f) => f.into_py(py),
+            Value::Select(s) => s.into_py(py),
         }
     }
 }
@@ -73,14 +134,14 @@ impl IntoPy<Py<PyAny>> for Value {
 #[pyclass(unsendable)]
 pub struct BluePrint {
     #[pyo3(get)]
-    pub variables: HashMap<String, Value>,
+    pub variables: IndexMap<String, Value>,
     #[pyo3(get)]
     pub modules: Vec<Module>,
 }
 impl From<&RsBluePrint> for BluePrint {
     fn from(bp: &RsBluePrint) -> Self {
         let variables = bp.variables.iter().map(value_to_pyvalue).collect();
-        let modules = bp.modules.iter().map(|b| Module::from(b)).collect();
+        let modules = bp.modules.iter().map(Module::from).collect();
         BluePrint { variables, modules }
     }
 }
@@ -98,7 +159,7 @@ impl BluePrint {
     #[staticmethod]
     #[pyo3(name = "from_file", signature = (path))]
     pub fn from_file(path: &str) -> PyResult<Self> {
-        let contents = std::fs::read_to_string(&path).map_err(|e| e.to_string());
+        let contents = std::fs::read_to_string(path).map_err(|e| e.to_string());
         let contents = match contents {
             Ok(c) => c,
             Err(e) => return Err(PyErr::new::<pyo3::exceptions::PyFileNotFoundError, _>(e)),
@@ -120,16 +181,17 @@ impl From<&RsValue> for Value {
     fn from(v: &RsValue) -> Self {
         match v {
             RsValue::String(s) => Value::String(s.to_owned()),
-            RsValue::Array(a) => Value::Array(a.iter().map(|x| Value::from(x)).collect()),
+            RsValue::Array(a) => Value::Array(a.iter().map(Value::from).collect()),
             RsValue::Boolean(b) => Value::Boolean(b.to_owned()),
-            RsValue::Map(d) => Value::Map(map_to_py(&d)),
+            RsValue::Map(d) => Value::Map(map_to_py(d)),
             RsValue::Ident(i) => Value::Ident(i.to_owned()),
             RsValue::Integer(i) => Value::Integer(i.to_owned()),
-            RsValue::ConcatExpr(c) => Value::ConcatExpr(c.iter().map(|x| Value::from(x)).collect()),
+            RsValue::ConcatExpr(c) => Value::ConcatExpr(c.iter().map(Value::from).collect()),
             RsValue::Function(f) => Value::Function(Function {
                 name: f.name.to_owned(),
-                args: f.args.iter().map(|x| Value::from(x)).collect(),
+                args: f.args.iter().map(Value::from).collect(),
             }),
+            RsValue::Select(s) => Value::Select(Select::from(s)),
         }
     }
 }
@@ -0,0 +1,705 @@
+//! lossless concrete syntax tree of a blueprint file
+//!
+//! Unlike [`BluePrint`](crate::BluePrint), the syntax tree keeps everything
+//! found in the input: whitespace, comments, trailing commas, the module form
+//! and the strings as they were written. Printing it back with `to_string()`
+//! gives the exact input, which makes it suitable for code rewriting tools.
+//!
+//! ```
+//! use android_bp::cst::File;
+//!
+//! let input = "// a comment\nfoo { name: \"a\\x41\", } // bar\n";
+//! let file = File::parse(input).unwrap();
+//! assert_eq!(file.to_string(), input);
+//! ```
+
+use crate::error::Error;
+use crate::string::{parse_raw_string, parse_string};
+use crate::utils::*;
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::multispace1,
+    combinator::{cut, eof, map, opt, recognize, verify},
+    error::{context, VerboseError},
+    multi::many0,
+    sequence::{pair, terminated, tuple},
+    Parser,
+};
+use std::fmt;
+
+/// a piece of text that carries no meaning
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub enum TriviaPiece {
+    Whitespace(String),
+    /// `// ...`, without the end of line
+    LineComment(String),
+    /// `/* ... */`
+    BlockComment(String),
+    /// a byte order mark, starting the file
+    ByteOrderMark,
+}
+
+/// whitespace and comments found before a token
+#[derive(Debug, PartialEq, Clone, Eq, Default)]
+pub struct Trivia(pub Vec<TriviaPiece>);
+impl Trivia {
+    /// iterate over the comments
+    pub fn comments(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().filter_map(|t| match t {
+            TriviaPiece::LineComment(c) | TriviaPiece::BlockComment(c) => Some(c.as_str()),
+            TriviaPiece::Whitespace(_) | TriviaPiece::ByteOrderMark => None,
+        })
+    }
+}
+
+/// kind of a token
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum TokenKind {
+    Ident,
+    /// a double quoted string
+    String,
+    /// a backtick quoted string
+    RawString,
+    Integer,
+    Boolean,
+    /// delimiters and operators: `{ } ( ) [ ] : = += + , @`
+    Punct,
+}
+
+/// a token, along with the trivia found before it
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub leading: Trivia,
+    /// text of the token, as written in the input
+    pub text: String,
+}
+
+/// an entry of a module or a map: `name: value,`
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Property {
+    /// identifier or string
+    pub name: Token,
+    /// `:`, or `=` in the second form of modules
+    pub colon: Token,
+    pub value: Expr,
+    pub comma: Option<Token>,
+}
+
+/// an element of a list, or an argument of a function call
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Element {
+    pub value: Expr,
+    pub comma: Option<Token>,
+}
+
+/// a case of a select: `pattern: value,`
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct SelectCase {
+    /// a string, a boolean, `default`, `any`, `any @ binding`, or a tuple of those
+    pub pattern: Expr,
+    pub colon: Token,
+    /// the value of the case, or the `unset` keyword
+    pub value: Expr,
+    pub comma: Option<Token>,
+}
+
+/// `select(conditions, { cases })`
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Select {
+    pub keyword: Token,
+    pub open: Token,
+    /// a call, or a tuple of calls
+    pub conditions: Expr,
+    pub comma: Token,
+    pub cases_open: Token,
+    pub cases: Vec<SelectCase>,
+    pub cases_close: Token,
+    pub trailing_comma: Option<Token>,
+    pub close: Token,
+}
+
+/// an expression
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub enum Expr {
+    /// a string, integer, boolean or variable reference
+    Literal(Token),
+    List {
+        open: Token,
+        elements: Vec<Element>,
+        close: Token,
+    },
+    Map {
+        open: Token,
+        properties: Vec<Property>,
+        close: Token,
+    },
+    Call {
+        name: Token,
+        open: Token,
+        args: Vec<Element>,
+        close: Token,
+    },
+    /// `lhs + rhs`
+    Binary {
+        lhs: Box<Expr>,
+        op: Token,
+        rhs: Box<Expr>,
+    },
+    Select(Box<Select>),
+    /// `(a, b)`, the conditions or the patterns of a select
+    Tuple {
+        open: Token,
+        elements: Vec<Element>,
+        close: Token,
+    },
+    /// `any @ binding`, in the patterns of a select
+    Binding { any: Token, at: Token, name: Token },
+}
+
+/// a module: `typ { ... }` or `typ(...)`
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Module {
+    pub typ: Token,
+    pub open: Token,
+    pub properties: Vec<Property>,
+    pub close: Token,
+}
+
+/// a variable definition: `name = value` or `name += value`
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Assignment {
+    pub name: Token,
+    pub op: Token,
+    pub value: Expr,
+}
+
+/// a top level item of a blueprint file
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub enum Item {
+    Module(Module),
+    Assignment(Assignment),
+}
+
+/// a blueprint file
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct File {
+    pub items: Vec<Item>,
+    /// trivia found after the last item
+    pub trailing: Trivia,
+}
+
+fn trivia(input: &str) -> VerboseResult<'_, Trivia> {
+    map(
+        many0(alt((
+            map(multispace1, |s: &str| TriviaPiece::Whitespace(s.to_string())),
+            map(recognize(comment), |s: &str| {
+                TriviaPiece::LineComment(s.to_string())
+            }),
+            map(recognize(multiline_comment), |s: &str| {
+                TriviaPiece::BlockComment(s.to_string())
+            }),
+        ))),
+        Trivia,
+    )(input)
+}
+fn token<'a, F, O>(
+    kind: TokenKind,
+    inner: F,
+) -> impl FnMut(&'a str) -> VerboseResult<'a, Token>
+where
+    F: Parser<&'a str, O, VerboseError<&'a str>>,
+{
+    map(pair(trivia, recognize(inner)), move |(leading, text)| Token {
+        kind,
+        leading,
+        text: text.to_string(),
+    })
+}
+fn punct<'a>(p: &'static str) -> impl FnMut(&'a str) -> VerboseResult<'a, Token> {
+    token(TokenKind::Punct, context(p, tag(p)))
+}
+/// an identifier, which is a boolean when it is `true` or `false`
+fn ident_token(input: &str) -> VerboseResult<'_, Token> {
+    map(token(TokenKind::Ident, identifier), |mut t| {
+        if t.text == "true" || t.text == "false" {
+            t.kind = TokenKind::Boolean;
+        }
+        t
+    })(input)
+}
+fn string_token(input: &str) -> VerboseResult<'_, Token> {
+    context(
+        "string",
+        alt((
+            token(TokenKind::String, parse_string::<VerboseError<&str>>),
+            token(TokenKind::RawString, parse_raw_string::<VerboseError<&str>>),
+        )),
+    )(input)
+}
+
+fn parse_properties<'a>(
+    input: &'a str,
+    delimiter: &'static str,
+) -> VerboseResult<'a, Vec<Property>> {
+    let mut properties = Vec::new();
+    let mut input = input;
+    loop {
+        let name = match alt((ident_token, string_token))(input) {
+            Ok((rest, name)) => {
+                input = rest;
+                name
+            }
+            Err(nom::Err::Error(_)) => break,
+            Err(e) => return Err(e),
+        };
+        let (rest, (colon, value, comma)) = context(
+            "module entry",
+            cut(tuple((
+                punct(delimiter),
+                parse_expr,
+                opt(punct(",")),
+            ))),
+        )(input)?;
+        input = rest;
+        let last = comma.is_none();
+        properties.push(Property {
+            name,
+            colon,
+            value,
+            comma,
+        });
+        if last {
+            break;
+        }
+    }
+    Ok((input, properties))
+}
+fn parse_elements(input: &str) -> VerboseResult<'_, Vec<Element>> {
+    parse_elements_of(input, parse_expr)
+}
+fn parse_elements_of<'a>(
+    input: &'a str,
+    item: fn(&'a str) -> VerboseResult<'a, Expr>,
+) -> VerboseResult<'a, Vec<Element>> {
+    let mut elements = Vec::new();
+    let mut input = input;
+    loop {
+        let value = match item(input) {
+            Ok((rest, value)) => {
+                input = rest;
+                value
+            }
+            Err(nom::Err::Error(_)) => break,
+            Err(e) => return Err(e),
+        };
+        let (rest, comma) = opt(punct(","))(input)?;
+        input = rest;
+        let last = comma.is_none();
+        elements.push(Element { value, comma });
+        if last {
+            break;
+        }
+    }
+    Ok((input, elements))
+}
+fn parse_list(input: &str) -> VerboseResult<'_, Expr> {
+    let (input, open) = punct("[")(input)?;
+    context(
+        "array",
+        cut(map(
+            pair(parse_elements, punct("]")),
+            move |(elements, close)| Expr::List {
+                open: open.clone(),
+                elements,
+                close,
+            },
+        )),
+    )(input)
+}
+fn parse_map(input: &str) -> VerboseResult<'_, Expr> {
+    let (input, open) = punct("{")(input)?;
+    context(
+        "dict",
+        cut(map(
+            pair(|i| parse_properties(i, ":"), punct("}")),
+            move |(properties, close)| Expr::Map {
+                open: open.clone(),
+                properties,
+                close,
+            },
+        )),
+    )(input)
+}
+fn parse_ident_or_call(input: &str) -> VerboseResult<'_, Expr> {
+    let (input, name) = ident_token(input)?;
+    match punct("(")(input) {
+        Ok((input, open)) => context(
+            "function",
+            cut(map(pair(parse_elements, punct(")")), move |(args, close)| {
+                Expr::Call {
+                    name: name.clone(),
+                    open: open.clone(),
+                    args,
+                    close,
+                }
+            })),
+        )(input),
+        Err(_) => Ok((input, Expr::Literal(name))),
+    }
+}
+/// `(item, item)`
+fn parse_tuple<'a>(
+    input: &'a str,
+    item: fn(&'a str) -> VerboseResult<'a, Expr>,
+) -> VerboseResult<'a, Expr> {
+    let (input, open) = punct("(")(input)?;
+    cut(map(
+        pair(|i| parse_elements_of(i, item), punct(")")),
+        move |(elements, close)| Expr::Tuple {
+            open: open.clone(),
+            elements,
+            close,
+        },
+    ))(input)
+}
+fn parse_condition(input: &str) -> VerboseResult<'_, Expr> {
+    context(
+        "condition",
+        verify(parse_ident_or_call, |e: &Expr| matches!(e, Expr::Call { .. })),
+    )(input)
+}
+fn parse_pattern(input: &str) -> VerboseResult<'_, Expr> {
+    context(
+        "pattern",
+        alt((
+            |i| parse_tuple(i, parse_pattern),
+            map(string_token, Expr::Literal),
+            parse_ident_or_binding,
+        )),
+    )(input)
+}
+fn parse_ident_or_binding(input: &str) -> VerboseResult<'_, Expr> {
+    let (input, any) = ident_token(input)?;
+    if any.text == "any" {
+        if let Ok((input, at)) = punct("@")(input) {
+            let (input, name) = cut(token(TokenKind::Ident, identifier))(input)?;
+            return Ok((input, Expr::Binding { any, at, name }));
+        }
+    }
+    Ok((input, Expr::Literal(any)))
+}
+/// the cases of a select on `conditions` conditions, each with one pattern per
+/// condition or a single `default`, which is found once at most
+fn parse_cases(input: &str, conditions: usize) -> VerboseResult<'_, Vec<SelectCase>> {
+    let mut cases = Vec::new();
+    let mut input = input;
+    let mut has_default = false;
+    loop {
+        let pattern = match parse_pattern(input) {
+            Ok((rest, pattern)) => {
+                // errors are reported at the pattern, after its trivia
+                let at = space_or_comments(input).map_or(input, |(r, _)| r);
+                let patterns = match &pattern {
+                    Expr::Tuple { elements, .. } => elements.len(),
+                    _ => 1,
+                };
+                match &pattern {
+                    Expr::Literal(t) if t.text == "default" && has_default => {
+                        return invalid(at, "select with a single default case");
+                    }
+                    Expr::Literal(t) if t.text == "default" => has_default = true,
+                    _ if patterns != conditions => {
+                        return invalid(at, "select case with one pattern per condition");
+                    }
+                    _ => {}
+                }
+                input = rest;
+                pattern
+            }
+            Err(nom::Err::Error(_)) => break,
+            Err(e) => return Err(e),
+        };
+        let (rest, (colon, value, comma)) = context(
+            "select case",
+            cut(tuple((punct(":"), parse_expr, opt(punct(","))))),
+        )(input)?;
+        input = rest;
+        let last = comma.is_none();
+        cases.push(SelectCase {
+            pattern,
+            colon,
+            value,
+            comma,
+        });
+        if last {
+            break;
+        }
+    }
+    Ok((input, cases))
+}
+fn parse_select(input: &str) -> VerboseResult<'_, Expr> {
+    let (input, keyword) = verify(ident_token, |t: &Token| t.text == "select")(input)?;
+    let (input, open) = punct("(")(input)?;
+    let (input, (conditions, comma, cases_open)) = context(
+        "select",
+        cut(tuple((
+            alt((
+                // a select needs at least one condition
+                verify(
+                    |i| parse_tuple(i, parse_condition),
+                    |e: &Expr| matches!(e, Expr::Tuple { elements, .. } if !elements.is_empty()),
+                ),
+                parse_condition,
+            )),
+            punct(","),
+            punct("{"),
+        ))),
+    )(input)?;
+    let count = match &conditions {
+        Expr::Tuple { elements, .. } => elements.len(),
+        _ => 1,
+    };
+    let (input, (cases, cases_close, trailing_comma, close)) = context(
+        "select",
+        cut(tuple((
+            |i| parse_cases(i, count),
+            punct("}"),
+            opt(punct(",")),
+            punct(")"),
+        ))),
+    )(input)?;
+    let select = Select {
+        keyword,
+        open,
+        conditions,
+        comma,
+        cases_open,
+        cases,
+        cases_close,
+        trailing_comma,
+        close,
+    };
+    Ok((input, Expr::Select(Box::new(select))))
+}
+fn parse_operand(input: &str) -> VerboseResult<'_, Expr> {
+    context(
+        "value",
+        alt((
+            parse_list,
+            parse_map,
+            parse_select,
+            map(string_token, Expr::Literal),
+            map(token(TokenKind::Integer, parse_int), Expr::Literal),
+            parse_ident_or_call,
+        )),
+    )(input)
+}
+fn parse_expr(input: &str) -> VerboseResult<'_, Expr> {
+    let (mut input, mut expr) = parse_operand(input)?;
+    while let Ok((rest, op)) = punct("+")(input) {
+        let (rest, rhs) = context("expr", cut(parse_operand))(rest)?;
+        input = rest;
+        expr = Expr::Binary {
+            lhs: Box::new(expr),
+            op,
+            rhs: Box::new(rhs),
+        };
+    }
+    Ok((input, expr))
+}
+fn parse_item(input: &str) -> VerboseResult<'_, Item> {
+    let (input, name) = token(TokenKind::Ident, identifier)(input)?;
+    if let Ok((input, open)) = punct("{")(input) {
+        let (input, (properties, close)) = context(
+            "module",
+            cut(pair(|i| parse_properties(i, ":"), punct("}"))),
+        )(input)?;
+        return Ok((
+            input,
+            Item::Module(Module {
+                typ: name,
+                open,
+                properties,
+                close,
+            }),
+        ));
+    }
+    if let Ok((input, open)) = punct("(")(input) {
+        let (input, (properties, close)) = context(
+            "module",
+            cut(pair(|i| parse_properties(i, "="), punct(")"))),
+        )(input)?;
+        return Ok((
+            input,
+            Item::Module(Module {
+                typ: name,
+                open,
+                properties,
+                close,
+            }),
+        ));
+    }
+    let (input, (op, value)) = context(
+        "define",
+        pair(alt((punct("+="), punct("="))), cut(parse_expr)),
+    )(input)?;
+    Ok((input, Item::Assignment(Assignment { name, op, value })))
+}
+fn parse_file(input: &str) -> VerboseResult<'_, File> {
+    let (input, bom) = opt(tag("\u{feff}"))(input)?;
+    let (input, mut file) = map(
+        terminated(pair(many0(parse_item), trivia), eof),
+        |(items, trailing)| File { items, trailing },
+    )(input)?;
+    // the byte order mark is the first trivia of the file
+    if bom.is_some() {
+        let leading = match file.items.first_mut() {
+            Some(Item::Module(m)) => &mut m.typ.leading,
+            Some(Item::Assignment(a)) => &mut a.name.leading,
+            None => &mut file.trailing,
+        };
+        leading.0.insert(0, TriviaPiece::ByteOrderMark);
+    }
+    Ok((input, file))
+}
+
+impl File {
+    /// parse a blueprint file into a lossless syntax tree
+    pub fn parse(input: &str) -> Result<Self, Error> {
+        match parse_file(input) {
+            Ok((_, file)) => Ok(file),
+            Err(err) => Err(Error::syntax(input, err)),
+        }
+    }
+}
+
+impl fmt::Display for Trivia {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for piece in &self.0 {
+            match piece {
+                TriviaPiece::Whitespace(s)
+                | TriviaPiece::LineComment(s)
+                | TriviaPiece::BlockComment(s) => f.write_str(s)?,
+                TriviaPiece::ByteOrderMark => f.write_str("\u{feff}")?,
+            }
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.leading, self.text)
+    }
+}
+fn write_opt(f: &mut fmt::Formatter<'_>, token: &Option<Token>) -> fmt::Result {
+    match token {
+        Some(t) => write!(f, "{}", t),
+        None => Ok(()),
+    }
+}
+impl fmt::Display for Property {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.name, self.colon, self.value)?;
+        write_opt(f, &self.comma)
+    }
+}
+impl fmt::Display for Element {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value)?;
+        write_opt(f, &self.comma)
+    }
+}
+impl fmt::Display for SelectCase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.pattern, self.colon, self.value)?;
+        write_opt(f, &self.comma)
+    }
+}
+impl fmt::Display for Select {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}{}{}",
+            self.keyword, self.open, self.conditions, self.comma, self.cases_open
+        )?;
+        self.cases.iter().try_for_each(|c| write!(f, "{}", c))?;
+        write!(f, "{}", self.cases_close)?;
+        write_opt(f, &self.trailing_comma)?;
+        write!(f, "{}", self.close)
+    }
+}
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Literal(t) => write!(f, "{}", t),
+            Expr::List {
+                open,
+                elements,
+                close,
+            } => {
+                write!(f, "{}", open)?;
+                elements.iter().try_for_each(|e| write!(f, "{}", e))?;
+                write!(f, "{}", close)
+            }
+            Expr::Map {
+                open,
+                properties,
+                close,
+            } => {
+                write!(f, "{}", open)?;
+                properties.iter().try_for_each(|p| write!(f, "{}", p))?;
+                write!(f, "{}", close)
+            }
+            Expr::Call {
+                name,
+                open,
+                args,
+                close,
+            } => {
+                write!(f, "{}{}", name, open)?;
+                args.iter().try_for_each(|e| write!(f, "{}", e))?;
+                write!(f, "{}", close)
+            }
+            Expr::Binary { lhs, op, rhs } => write!(f, "{}{}{}", lhs, op, rhs),
+            Expr::Select(s) => write!(f, "{}", s),
+            Expr::Tuple {
+                open,
+                elements,
+                close,
+            } => {
+                write!(f, "{}", open)?;
+                elements.iter().try_for_each(|e| write!(f, "{}", e))?;
+                write!(f, "{}", close)
+            }
+            Expr::Binding { any, at, name } => write!(f, "{}{}{}", any, at, name),
+        }
+    }
+}
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.typ, self.open)?;
+        self.properties.iter().try_for_each(|p| write!(f, "{}", p))?;
+        write!(f, "{}", self.close)
+    }
+}
+impl fmt::Display for Assignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.name, self.op, self.value)
+    }
+}
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Item::Module(m) => write!(f, "{}", m),
+            Item::Assignment(a) => write!(f, "{}", a),
+        }
+    }
+}
+impl fmt::Display for File {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.items.iter().try_for_each(|i| write!(f, "{}", i))?;
+        write!(f, "{}", self.trailing)
+    }
+}
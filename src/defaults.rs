@@ -0,0 +1,122 @@
+use crate::error::Error;
+use crate::expr::ExprKind;
+use crate::parser::{BluePrint, Module, Value};
+
+/// properties of a defaults module which apply to the defaults module itself
+const NOT_DEFAULTABLE: &[&str] = &["name", "defaults", "visibility", "defaults_visibility"];
+
+/// merge the value of a property of a defaults module into the value of a module,
+/// as soong prepends the properties of defaults: lists are prepended, maps are
+/// merged, and other values are only taken when the module does not set them.
+fn prepend(value: Value, defaults: &Value) -> Result<Value, String> {
+    Ok(match (value, defaults) {
+        (Value::Map(mut m), Value::Map(d)) => {
+            m.merge_with(d, prepend)?;
+            Value::Map(m)
+        }
+        (v, d) if matches!(v, Value::Array(_)) || matches!(d, Value::Array(_)) => d.clone().plus(v)?,
+        (v, _) => v,
+    })
+}
+
+impl Module {
+    /// the defaults modules of this module, walking the `defaults` properties
+    /// depth first, as soong does. Each module is listed once.
+    fn collect_defaults<'a>(
+        &self,
+        find: &impl Fn(&str) -> Option<&'a Module>,
+        stack: &mut Vec<String>,
+        list: &mut Vec<&'a Module>,
+    ) -> Result<(), Error> {
+        let Some(Value::Array(names)) = self.get("defaults") else {
+            return Ok(());
+        };
+        let property_span = self.value_span("defaults").unwrap_or(self.span());
+        let spans = match self.expr("defaults").map(|e| &e.kind) {
+            Some(ExprKind::List(l)) if l.len() == names.len() => l.iter().map(|e| e.span()).collect(),
+            _ => vec![property_span; names.len()],
+        };
+        for (name, span) in names.iter().zip(spans) {
+            let Value::String(name) = name else {
+                return Err(Error::semantic(span, "defaults must be a list of module names"));
+            };
+            if stack.contains(name) {
+                return Err(Error::semantic(
+                    span,
+                    format!("defaults cycle: {} -> {}", stack.join(" -> "), name),
+                ));
+            }
+            let defaults = find(name).ok_or_else(|| {
+                Error::semantic(span, format!("\"defaults\" depends on undefined module \"{}\"", name))
+            })?;
+            if !defaults.typ.ends_with("defaults") {
+                return Err(Error::semantic(
+                    span,
+                    format!("module \"{}\" is not a defaults module", name),
+                ));
+            }
+            if list.iter().any(|m| std::ptr::eq(*m, defaults)) {
+                continue;
+            }
+            list.push(defaults);
+            stack.push(name.clone());
+            defaults.collect_defaults(find, stack, list)?;
+            stack.pop();
+        }
+        Ok(())
+    }
+    /// the module with the properties of its defaults modules applied, recursively.
+    ///
+    /// `find` looks up a module by name, in the same file or in the rest of the tree.
+    /// Lists of the defaults come before the lists of the module, maps are merged,
+    /// and other properties are taken from the defaults only when the module does
+    /// not set them. The module should be evaluated first, see [`BluePrint::evaluate`].
+    ///
+    /// Missing defaults modules and cycles are reported at the `defaults` property.
+    pub fn with_defaults<'a>(&self, find: impl Fn(&str) -> Option<&'a Module>) -> Result<Module, Error> {
+        let mut stack = vec![self.get_string("name").cloned().unwrap_or_default()];
+        let mut list = Vec::new();
+        self.collect_defaults(&find, &mut stack, &mut list)?;
+        let mut module = self.clone();
+        for defaults in list {
+            let defaults_name = defaults.get_string("name").map(String::as_str).unwrap_or_default();
+            for (k, dv) in defaults.entries.iter() {
+                if NOT_DEFAULTABLE.contains(&k.as_str()) {
+                    continue;
+                }
+                let value = match module.entries.get(k) {
+                    Some(v) => prepend(v.clone(), dv).map_err(|e| {
+                        let span = module.value_span(k).unwrap_or(module.span());
+                        let message = format!(
+                            "cannot apply defaults \"{}\" to property \"{}\": {}",
+                            defaults_name, k, e
+                        );
+                        Error::semantic(span, message)
+                    })?,
+                    None => {
+                        if let Some(span) = defaults.spans.entries.get(k) {
+                            module.spans.entries.insert(k.clone(), *span);
+                        }
+                        dv.clone()
+                    }
+                };
+                module.set(k, value);
+            }
+        }
+        Ok(module)
+    }
+}
+
+impl BluePrint {
+    /// get a module by name
+    pub fn module(&self, name: &str) -> Option<&Module> {
+        self.modules
+            .iter()
+            .find(|m| m.get_string("name").map(String::as_str) == Some(name))
+    }
+    /// apply the defaults of a module, looking them up in this file,
+    /// see [`Module::with_defaults`]
+    pub fn with_defaults(&self, module: &Module) -> Result<Module, Error> {
+        module.with_defaults(|name| self.module(name))
+    }
+}
@@ -0,0 +1,378 @@
+//! human and machine readable diagnostics
+//!
+//! [`Error`]s are turned into [`Diagnostic`]s, which carry a hint when the
+//! cause of the error can be guessed. They can be rendered like rustc does,
+//! or serialized to JSON or SARIF for code review tools.
+//!
+//! ```
+//! use android_bp::{BluePrint, Diagnostic};
+//!
+//! let input = "foo {\n    name: \"foo\"\n    srcs: [],\n}\n";
+//! let (_, errors) = BluePrint::parse_recovering(input);
+//! let diagnostics: Vec<_> = errors.iter().map(|e| Diagnostic::from_error(e, input)).collect();
+//! assert_eq!(diagnostics[0].hint.as_deref(), Some("missing comma between properties"));
+//! println!("{}", diagnostics[0].render(input));
+//! println!("{}", android_bp::diagnostic::to_sarif(&diagnostics));
+//! ```
+
+use crate::error::Error;
+use crate::json::Json;
+use crate::span::{LineIndex, Position, Span};
+use crate::utils::{identifier, string_literal};
+use std::path::PathBuf;
+
+/// how bad a diagnostic is
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+impl Severity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// a problem found in a blueprint file, ready to be reported
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// kind of problem: `io`, `syntax` or `semantic`
+    pub code: &'static str,
+    pub message: String,
+    pub file: Option<PathBuf>,
+    pub span: Option<Span>,
+    /// a guess of how to fix the problem
+    pub hint: Option<String>,
+    /// other locations involved, like the previous definition of a property
+    pub notes: Vec<(Span, String)>,
+}
+
+/// offset of the opening quote of the first string, starting before `limit`,
+/// that is not closed on its line, or of the first unclosed raw string
+fn unterminated_string(source: &str, limit: usize) -> Option<usize> {
+    let bytes = source.as_bytes();
+    let mut i = 0;
+    while i <= limit && i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i = source[i..].find('\n').map_or(bytes.len(), |n| i + n);
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = source[i + 2..].find("*/").map_or(bytes.len(), |n| i + n + 4);
+            }
+            b'"' => {
+                let start = i;
+                i += 1;
+                loop {
+                    match bytes.get(i) {
+                        None | Some(b'\n') => return Some(start),
+                        Some(b'\\') => i += 2,
+                        Some(b'"') => break,
+                        Some(_) => i += 1,
+                    }
+                }
+                i += 1;
+            }
+            b'`' => match source[i + 1..].find('`') {
+                Some(n) => i += n + 2,
+                None => return Some(i),
+            },
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// `text` starts with an identifier or a string, followed by `delimiter`
+fn starts_entry(text: &str, delimiter: char) -> bool {
+    let rest = match identifier(text) {
+        Ok((rest, _)) => rest,
+        Err(_) => match string_literal(text) {
+            Ok((rest, _)) => rest,
+            Err(_) => return false,
+        },
+    };
+    let rest = rest.trim_start();
+    rest.starts_with(delimiter) && !rest.starts_with("==")
+}
+
+/// guess the cause of a syntax error at `offset`
+fn syntax_hint(source: &str, offset: usize, expected: Option<&str>, context: &[&str]) -> Option<&'static str> {
+    let text = &source[offset..];
+    let in_module = context.first() == Some(&"module") || context.first() == Some(&"dict");
+    if context.first() == Some(&"escape sequence") {
+        return Some("invalid escape sequence, see go's strconv.Unquote");
+    }
+    match expected {
+        Some("'}'") if starts_entry(text, '=') => Some("use ':' inside modules, '=' at top level"),
+        Some("'}'") if starts_entry(text, ':') => Some("missing comma between properties"),
+        Some("')'") if starts_entry(text, '=') => Some("missing comma between properties"),
+        Some("']'") if !text.starts_with(']') && !text.is_empty() => {
+            Some("missing comma between list elements")
+        }
+        _ if !in_module && (text.starts_with(':') || starts_entry(text, ':')) => {
+            Some("use ':' inside modules, '=' at top level")
+        }
+        _ => None,
+    }
+}
+
+/// span of the token starting at `offset`
+fn token_span(index: &LineIndex, source: &str, offset: usize) -> Span {
+    let text = &source[offset..];
+    let len = match identifier(text) {
+        Ok((rest, _)) => text.len() - rest.len(),
+        Err(_) => text.chars().next().map_or(0, |c| c.len_utf8()),
+    };
+    Span {
+        start: index.position(offset),
+        end: index.position(offset + len),
+    }
+}
+
+/// the location of `span`, followed by its line of `source` with the span
+/// underlined, or only the file when the span has no location
+fn snippet(source: &str, file: &str, span: &Span) -> String {
+    if !span.is_known() {
+        return format!(" --> {}\n", file);
+    }
+    let line_number = span.start.line.to_string();
+    let gutter = " ".repeat(line_number.len());
+    let line = source.lines().nth(span.start.line - 1).unwrap_or_default();
+    // columns do not count a leading byte order mark
+    let line = if span.start.line == 1 { line.trim_start_matches('\u{feff}') } else { line };
+    let prefix: String = line.chars().take(span.start.column - 1).collect();
+    let width = if span.end.line == span.start.line {
+        span.end.column.saturating_sub(span.start.column)
+    } else {
+        line.chars().count().saturating_sub(span.start.column - 1)
+    };
+    let mut out = format!("{}--> {}:{}:{}\n", gutter, file, span.start.line, span.start.column);
+    out += &format!("{} |\n", gutter);
+    out += &format!("{} | {}\n", line_number, line.replace('\t', "    "));
+    out += &format!(
+        "{} | {}{}\n",
+        gutter,
+        " ".repeat(prefix.replace('\t', "    ").chars().count()),
+        "^".repeat(width.max(1))
+    );
+    out
+}
+
+impl Diagnostic {
+    /// build a diagnostic from an error found in `source`
+    pub fn from_error(error: &Error, source: &str) -> Self {
+        let index = LineIndex::new(source);
+        let mut diagnostic = Diagnostic {
+            severity: Severity::Error,
+            code: match error {
+                Error::Io { .. } => "io",
+                Error::Syntax { .. } => "syntax",
+                Error::Semantic { .. } => "semantic",
+            },
+            message: error.message(),
+            file: error.file().map(|f| f.to_path_buf()),
+            span: None,
+            hint: None,
+            notes: error.notes().to_vec(),
+        };
+        match error {
+            Error::Io { .. } => {}
+            Error::Syntax {
+                position,
+                expected,
+                context,
+                ..
+            } => {
+                let offset = position.offset.min(source.len());
+                if let Some(start) = unterminated_string(source, offset) {
+                    let end = source[start..].find('\n').map_or(source.len(), |n| start + n);
+                    diagnostic.span = Some(Span {
+                        start: index.position(start),
+                        end: index.position(end),
+                    });
+                    diagnostic.hint = Some("unterminated string".to_string());
+                } else {
+                    diagnostic.span = Some(token_span(&index, source, offset));
+                    diagnostic.hint = syntax_hint(source, offset, expected.as_deref(), context)
+                        .map(|h| h.to_string());
+                }
+            }
+            Error::Semantic { span, .. } => diagnostic.span = Some(*span).filter(Span::is_known),
+        }
+        diagnostic
+    }
+
+    /// render the diagnostic like rustc does, with the offending line of `source`
+    pub fn render(&self, source: &str) -> String {
+        let mut out = format!("{}: {}\n", self.severity.as_str(), self.message);
+        let file = self
+            .file
+            .as_ref()
+            .map_or("<input>".to_string(), |f| f.display().to_string());
+        let Some(span) = self.span else {
+            out += &format!(" --> {}\n", file);
+            if let Some(hint) = &self.hint {
+                out += &format!("  = help: {}\n", hint);
+            }
+            return out;
+        };
+        let gutter = " ".repeat(span.start.line.to_string().len());
+        out += &snippet(source, &file, &span);
+        if let Some(hint) = &self.hint {
+            out += &format!("{} |\n{} = help: {}\n", gutter, gutter, hint);
+        }
+        for (span, note) in &self.notes {
+            out += &format!("note: {}\n", note);
+            out += &snippet(source, &file, span);
+        }
+        out
+    }
+
+    fn to_json(&self) -> Json {
+        let position = |p: &Position| {
+            Json::object([
+                ("offset", p.offset.into()),
+                ("line", p.line.into()),
+                ("column", p.column.into()),
+            ])
+        };
+        let mut entries = vec![
+            ("severity", self.severity.as_str().into()),
+            ("code", self.code.into()),
+            ("message", self.message.as_str().into()),
+        ];
+        if let Some(file) = &self.file {
+            entries.push(("file", file.to_string_lossy().as_ref().into()));
+        }
+        if let Some(span) = &self.span {
+            entries.push((
+                "span",
+                Json::object([("start", position(&span.start)), ("end", position(&span.end))]),
+            ));
+        }
+        if let Some(hint) = &self.hint {
+            entries.push(("hint", hint.as_str().into()));
+        }
+        if !self.notes.is_empty() {
+            let notes = self.notes.iter().map(|(span, note)| {
+                let mut note = vec![("message", note.as_str().into())];
+                if span.is_known() {
+                    note.push((
+                        "span",
+                        Json::object([("start", position(&span.start)), ("end", position(&span.end))]),
+                    ));
+                }
+                Json::object(note)
+            });
+            entries.push(("notes", Json::Array(notes.collect())));
+        }
+        Json::object(entries)
+    }
+
+    /// SARIF physical location of `span` in the file of the diagnostic
+    fn sarif_location(&self, span: Option<&Span>) -> Vec<(&'static str, Json)> {
+        let mut location = Vec::new();
+        if let Some(file) = &self.file {
+            location.push((
+                "artifactLocation",
+                Json::object([("uri", file.to_string_lossy().replace('\\', "/").as_str().into())]),
+            ));
+        }
+        if let Some(span) = span {
+            location.push((
+                "region",
+                Json::object([
+                    ("startLine", span.start.line.into()),
+                    ("startColumn", span.start.column.into()),
+                    ("endLine", span.end.line.into()),
+                    ("endColumn", span.end.column.into()),
+                    ("byteOffset", span.start.offset.into()),
+                    ("byteLength", (span.end.offset - span.start.offset).into()),
+                ]),
+            ));
+        }
+        location
+    }
+
+    fn to_sarif(&self) -> Json {
+        let mut text = self.message.clone();
+        if let Some(hint) = &self.hint {
+            text += &format!("\nhelp: {}", hint);
+        }
+        let location = self.sarif_location(self.span.as_ref());
+        let mut result = vec![
+            ("ruleId", self.code.into()),
+            ("level", self.severity.as_str().into()),
+            ("message", Json::object([("text", text.as_str().into())])),
+        ];
+        if !location.is_empty() {
+            result.push((
+                "locations",
+                Json::Array(vec![Json::object([(
+                    "physicalLocation",
+                    Json::object(location),
+                )])]),
+            ));
+        }
+        if !self.notes.is_empty() {
+            let related = self.notes.iter().map(|(span, note)| {
+                Json::object([
+                    ("message", Json::object([("text", note.as_str().into())])),
+                    (
+                        "physicalLocation",
+                        Json::object(self.sarif_location(Some(span).filter(|s| s.is_known()))),
+                    ),
+                ])
+            });
+            result.push(("relatedLocations", Json::Array(related.collect())));
+        }
+        Json::object(result)
+    }
+}
+
+/// serialize diagnostics to a JSON array
+pub fn to_json(diagnostics: &[Diagnostic]) -> String {
+    Json::Array(diagnostics.iter().map(|d| d.to_json()).collect()).to_string()
+}
+
+/// serialize diagnostics to a SARIF 2.1.0 log
+pub fn to_sarif(diagnostics: &[Diagnostic]) -> String {
+    let rule = |id: &str, text: &str| {
+        Json::object([
+            ("id", id.into()),
+            ("shortDescription", Json::object([("text", text.into())])),
+        ])
+    };
+    let driver = Json::object([
+        ("name", "android_bp".into()),
+        ("version", env!("CARGO_PKG_VERSION").into()),
+        ("informationUri", env!("CARGO_PKG_REPOSITORY").into()),
+        (
+            "rules",
+            Json::Array(vec![
+                rule("io", "the file could not be read"),
+                rule("syntax", "the file does not follow the blueprint grammar"),
+                rule("semantic", "the file is well formed, but does not make sense"),
+            ]),
+        ),
+    ]);
+    let run = Json::object([
+        ("tool", Json::object([("driver", driver)])),
+        ("columnKind", "unicodeCodePoints".into()),
+        (
+            "results",
+            Json::Array(diagnostics.iter().map(|d| d.to_sarif()).collect()),
+        ),
+    ]);
+    Json::object([
+        ("$schema", "https://json.schemastore.org/sarif-2.1.0.json".into()),
+        ("version", "2.1.0".into()),
+        ("runs", Json::Array(vec![run])),
+    ])
+    .to_string()
+}
@@ -0,0 +1,174 @@
+use crate::span::{LineIndex, Position, Span};
+use nom::error::{convert_error, VerboseError, VerboseErrorKind};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// an error raised while reading a blueprint file
+#[derive(Debug)]
+pub enum Error {
+    /// the file could not be read
+    Io {
+        file: PathBuf,
+        error: std::io::Error,
+    },
+    /// the input does not follow the blueprint grammar
+    Syntax {
+        file: Option<PathBuf>,
+        position: Position,
+        /// what the parser was looking for, e.g. `'}'`
+        expected: Option<String>,
+        /// the grammar rules being parsed, innermost first,
+        /// e.g. `["array", "value", "expr", "module entry", "module", "blueprint"]`
+        context: Vec<&'static str>,
+        /// human readable rendering of the error, with the offending lines
+        rendered: String,
+    },
+    /// the input is well formed, but does not make sense, like appending a
+    /// string to an integer variable
+    Semantic {
+        file: Option<PathBuf>,
+        span: Span,
+        message: String,
+        /// other locations involved, like the previous definition of a property
+        notes: Vec<(Span, String)>,
+    },
+}
+
+impl Error {
+    /// build a syntax error from a nom error on `input`
+    pub(crate) fn syntax(input: &str, err: nom::Err<VerboseError<&str>>) -> Self {
+        let e = match err {
+            nom::Err::Error(e) | nom::Err::Failure(e) => e,
+            nom::Err::Incomplete(_) => {
+                return Error::Syntax {
+                    file: None,
+                    position: LineIndex::new(input).position(input.len()),
+                    expected: None,
+                    context: Vec::new(),
+                    rendered: "Incomplete".to_string(),
+                }
+            }
+        };
+        let offset = e
+            .errors
+            .first()
+            .map(|(rest, _)| input.len() - rest.len())
+            .unwrap_or_default();
+        // delimiters are labelled with their own text, other labels are grammar rules
+        let is_token = |label: &str| !label.chars().any(|c| c.is_alphanumeric());
+        let expected = e.errors.iter().find_map(|(_, kind)| match kind {
+            VerboseErrorKind::Char(c) => Some(format!("'{}'", c)),
+            VerboseErrorKind::Context(c) if is_token(c) => Some(format!("'{}'", c)),
+            _ => None,
+        });
+        let context = e
+            .errors
+            .iter()
+            .filter_map(|(_, kind)| match kind {
+                VerboseErrorKind::Context(c) if !is_token(c) => Some(*c),
+                _ => None,
+            })
+            .collect();
+        Error::Syntax {
+            file: None,
+            position: LineIndex::new(input).position(offset),
+            expected,
+            context,
+            rendered: convert_error(input, e),
+        }
+    }
+    pub(crate) fn semantic(span: Span, message: impl Into<String>) -> Self {
+        Error::Semantic {
+            file: None,
+            span,
+            message: message.into(),
+            notes: Vec::new(),
+        }
+    }
+    /// attach another location to a semantic error
+    pub(crate) fn with_note(mut self, span: Span, note: impl Into<String>) -> Self {
+        if let Error::Semantic { notes, .. } = &mut self {
+            notes.push((span, note.into()));
+        }
+        self
+    }
+    /// attach the file the error was found in
+    pub(crate) fn in_file(mut self, path: &Path) -> Self {
+        match &mut self {
+            Error::Io { .. } => {}
+            Error::Syntax { file, .. } | Error::Semantic { file, .. } => {
+                *file = Some(path.to_path_buf())
+            }
+        }
+        self
+    }
+    /// file the error was found in, if known
+    pub fn file(&self) -> Option<&Path> {
+        match self {
+            Error::Io { file, .. } => Some(file),
+            Error::Syntax { file, .. } | Error::Semantic { file, .. } => file.as_deref(),
+        }
+    }
+    /// one line description of the error, without its location
+    pub fn message(&self) -> String {
+        match self {
+            Error::Io { error, .. } => error.to_string(),
+            Error::Syntax {
+                expected, context, ..
+            } => {
+                let mut message = "syntax error".to_string();
+                if let Some(expected) = expected {
+                    message += &format!(", expected {}", expected);
+                }
+                if let Some(c) = context.first() {
+                    message += &format!(" in {}", c);
+                }
+                message
+            }
+            Error::Semantic { message, .. } => message.clone(),
+        }
+    }
+    /// other locations involved in the error, with a description of each
+    pub fn notes(&self) -> &[(Span, String)] {
+        match self {
+            Error::Semantic { notes, .. } => notes,
+            _ => &[],
+        }
+    }
+    /// position of the error in the input, if any. Errors about values which
+    /// were not parsed, see [`Span::is_known`], have none.
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            Error::Io { .. } => None,
+            Error::Syntax { position, .. } => Some(*position),
+            Error::Semantic { span, .. } => Some(span.start).filter(|_| span.is_known()),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = self.file() {
+            write!(f, "{}:", file.display())?;
+        }
+        if let Some(p) = self.position() {
+            write!(f, "{}:{}: ", p.line, p.column)?;
+        } else if self.file().is_some() {
+            f.write_str(" ")?;
+        }
+        f.write_str(&self.message())?;
+        if let Error::Syntax { rendered, .. } = self {
+            write!(f, "\n{}", rendered)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
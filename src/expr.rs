@@ -0,0 +1,312 @@
+//! expressions, as written in a blueprint file
+//!
+//! An [`Expr`] is what the file says: literals, references to variables, `+`
+//! operations, calls and selects, each with its location. Evaluating it gives
+//! what the file means: a [`Value`] in which variables are substituted and
+//! every `+` is computed.
+//!
+//! ```
+//! use android_bp::{BluePrint, IndexMap, Value};
+//!
+//! let bp = BluePrint::parse("common = [\"x.c\"]\nfoo { srcs: common + [\"b.c\"] }").unwrap();
+//! let srcs = bp.modules[0].expr("srcs").unwrap();
+//! let mut variables = IndexMap::new();
+//! variables.insert("common".to_string(), Value::Array(vec!["a.c".into()]));
+//! assert_eq!(
+//!     srcs.evaluate(&variables).unwrap(),
+//!     Value::Array(vec!["a.c".into(), "b.c".into()])
+//! );
+//! ```
+
+use crate::error::Error;
+use crate::parser::{entries_with_spans, fold_values, Function, Map, Select, SelectCase};
+use crate::span::{EntrySpan, LineIndex, SourceInfo, Span};
+use crate::{Condition, SelectPattern, Value};
+use indexmap::IndexMap;
+
+/// an expression, with its location
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub(crate) span: SourceInfo<Span>,
+}
+
+/// the different kinds of expressions
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub enum ExprKind {
+    /// a string, with its quoting: `raw` when it was written between
+    /// backticks, so that it can be printed back the same way
+    String { value: String, raw: bool },
+    Integer(i64),
+    Boolean(bool),
+    List(Vec<Expr>),
+    /// properties in source order, including the keys defined twice
+    Map(Vec<Property>),
+    /// a reference to a variable
+    Variable(String),
+    /// `lhs + rhs`
+    Add(Box<Expr>, Box<Expr>),
+    /// `name(args)`
+    Call(String, Vec<Expr>),
+    Select(SelectExpr),
+}
+
+/// a `name: value` entry of a map
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Property {
+    pub name: String,
+    pub value: Expr,
+    pub(crate) name_span: SourceInfo<Span>,
+}
+
+/// a `select(conditions, { cases })` expression
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct SelectExpr {
+    pub conditions: Vec<Condition>,
+    /// cases, in the order they were written
+    pub cases: Vec<Case>,
+}
+
+/// a case of a select: `("arm64", default): value`
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Case {
+    /// one pattern per condition, or a single `default`
+    pub patterns: Vec<SelectPattern>,
+    /// the value of the case, `None` for `unset`
+    pub value: Option<Expr>,
+}
+
+impl Property {
+    /// get the span of the name of the property
+    pub fn name_span(&self) -> Span {
+        *self.name_span
+    }
+}
+
+impl Expr {
+    pub(crate) fn new(kind: ExprKind, span: Span) -> Self {
+        Expr {
+            kind,
+            span: SourceInfo(span),
+        }
+    }
+    /// get the span of the expression
+    pub fn span(&self) -> Span {
+        *self.span
+    }
+    pub(crate) fn resolve_spans(&mut self, index: &LineIndex) {
+        index.resolve(&mut self.span);
+        match &mut self.kind {
+            ExprKind::List(l) | ExprKind::Call(_, l) => {
+                l.iter_mut().for_each(|e| e.resolve_spans(index))
+            }
+            ExprKind::Map(m) => m.iter_mut().for_each(|p| {
+                index.resolve(&mut p.name_span);
+                p.value.resolve_spans(index);
+            }),
+            ExprKind::Add(lhs, rhs) => {
+                lhs.resolve_spans(index);
+                rhs.resolve_spans(index);
+            }
+            ExprKind::Select(s) => s
+                .cases
+                .iter_mut()
+                .filter_map(|c| c.value.as_mut())
+                .for_each(|e| e.resolve_spans(index)),
+            _ => {}
+        }
+    }
+    /// the variables referenced by the expression, with the span of each
+    /// reference, in source order
+    pub fn references(&self) -> Vec<(&str, Span)> {
+        match &self.kind {
+            ExprKind::Variable(v) => vec![(v.as_str(), self.span())],
+            ExprKind::List(l) | ExprKind::Call(_, l) => l.iter().flat_map(Expr::references).collect(),
+            ExprKind::Map(m) => m.iter().flat_map(|p| p.value.references()).collect(),
+            ExprKind::Add(lhs, rhs) => {
+                let mut references = lhs.references();
+                references.extend(rhs.references());
+                references
+            }
+            ExprKind::Select(s) => s
+                .cases
+                .iter()
+                .flat_map(|c| {
+                    // names bound by `any @ x` are not variables of the file
+                    let bound = |name: &str| {
+                        c.patterns
+                            .iter()
+                            .any(|p| matches!(p, SelectPattern::Any(Some(b)) if b == name))
+                    };
+                    c.value
+                        .iter()
+                        .flat_map(Expr::references)
+                        .filter(move |(name, _)| !bound(name))
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+    /// the operands of a chain of `+`, from left to right
+    fn operands(&self) -> Vec<&Expr> {
+        match &self.kind {
+            ExprKind::Add(lhs, rhs) => {
+                let mut operands = lhs.operands();
+                operands.extend(rhs.operands());
+                operands
+            }
+            _ => vec![self],
+        }
+    }
+    /// the value of the expression, computing what does not depend on
+    /// variables, and keeping the rest as `Ident` and `ConcatExpr`
+    pub(crate) fn to_value(&self) -> Value {
+        match &self.kind {
+            ExprKind::String { value, .. } => Value::String(value.clone()),
+            ExprKind::Integer(i) => Value::Integer(*i),
+            ExprKind::Boolean(b) => Value::Boolean(*b),
+            ExprKind::List(l) => Value::Array(l.iter().map(Expr::to_value).collect()),
+            ExprKind::Map(m) => self.map_value(m.iter().map(|p| p.value.to_value())),
+            ExprKind::Variable(v) => Value::Ident(v.clone()),
+            ExprKind::Add(..) => fold_values(self.operands().into_iter().map(Expr::to_value).collect()),
+            ExprKind::Call(name, args) => Value::Function(Function {
+                name: name.clone(),
+                args: args.iter().map(Expr::to_value).collect(),
+            }),
+            ExprKind::Select(s) => Value::Select(Select {
+                conditions: s.conditions.clone(),
+                cases: s
+                    .cases
+                    .iter()
+                    .map(|c| SelectCase {
+                        patterns: c.patterns.clone(),
+                        value: c.value.as_ref().map(Expr::to_value),
+                    })
+                    .collect(),
+            }),
+        }
+    }
+    /// build a map from the values of the properties of this map expression
+    fn map_value(&self, values: impl Iterator<Item = Value>) -> Value {
+        let ExprKind::Map(properties) = &self.kind else {
+            unreachable!("not a map expression");
+        };
+        let entries = properties
+            .iter()
+            .zip(values)
+            .map(|(p, v)| {
+                let span = EntrySpan {
+                    key: p.name_span(),
+                    value: p.value.span(),
+                };
+                (p.name.clone(), v, span)
+            })
+            .collect();
+        let (entries, spans) = entries_with_spans((entries, self.span()));
+        Value::Map(Map(entries, SourceInfo(Box::new(spans))))
+    }
+    /// evaluate the expression, substituting `variables`, which must be
+    /// evaluated already, and computing every `+`.
+    ///
+    /// Selects and calls are kept, with their arguments evaluated, as they
+    /// depend on the configuration. The `any @ x` bindings of selects are kept
+    /// as `Ident`s, see [`crate::Module::with_configuration`].
+    pub fn evaluate(&self, variables: &IndexMap<String, Value>) -> Result<Value, Error> {
+        Ok(match &self.kind {
+            ExprKind::String { .. } | ExprKind::Integer(_) | ExprKind::Boolean(_) => self.to_value(),
+            ExprKind::List(l) => Value::Array(
+                l.iter()
+                    .map(|e| e.evaluate(variables))
+                    .collect::<Result<_, _>>()?,
+            ),
+            ExprKind::Map(m) => {
+                let values = m
+                    .iter()
+                    .map(|p| p.value.evaluate(variables))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.map_value(values.into_iter())
+            }
+            ExprKind::Variable(v) => variables.get(v).cloned().ok_or_else(|| {
+                Error::semantic(self.span(), format!("variable `{}` not found", v))
+            })?,
+            ExprKind::Add(lhs, rhs) => lhs
+                .evaluate(variables)?
+                .plus(rhs.evaluate(variables)?)
+                .map_err(|e| Error::semantic(self.span(), e))?,
+            ExprKind::Call(name, args) => Value::Function(Function {
+                name: name.clone(),
+                args: args
+                    .iter()
+                    .map(|e| e.evaluate(variables))
+                    .collect::<Result<_, _>>()?,
+            }),
+            ExprKind::Select(s) => Value::Select(Select {
+                conditions: s.conditions.clone(),
+                cases: s
+                    .cases
+                    .iter()
+                    .map(|c| {
+                        // `any @ x` bindings are only known once the select is resolved
+                        let mut bound = None;
+                        for p in &c.patterns {
+                            if let SelectPattern::Any(Some(binding)) = p {
+                                let bound = bound.get_or_insert_with(|| variables.clone());
+                                bound.insert(binding.clone(), Value::Ident(binding.clone()));
+                            }
+                        }
+                        let variables = bound.as_ref().unwrap_or(variables);
+                        Ok(SelectCase {
+                            patterns: c.patterns.clone(),
+                            value: c.value.as_ref().map(|e| e.evaluate(variables)).transpose()?,
+                        })
+                    })
+                    .collect::<Result<_, Error>>()?,
+            }),
+        })
+    }
+}
+
+/// the expression of a value, without location, see [`Span::is_known`]
+impl From<&Value> for Expr {
+    fn from(value: &Value) -> Self {
+        let kind = match value {
+            Value::String(s) => ExprKind::String {
+                value: s.clone(),
+                raw: false,
+            },
+            Value::Integer(i) => ExprKind::Integer(*i),
+            Value::Boolean(b) => ExprKind::Boolean(*b),
+            Value::Array(a) => ExprKind::List(a.iter().map(Expr::from).collect()),
+            Value::Map(m) => ExprKind::Map(
+                m.iter()
+                    .map(|(k, v)| Property {
+                        name: k.clone(),
+                        value: v.into(),
+                        name_span: Default::default(),
+                    })
+                    .collect(),
+            ),
+            Value::Ident(i) => ExprKind::Variable(i.clone()),
+            Value::ConcatExpr(values) => {
+                let mut operands = values.iter().map(Expr::from);
+                let first = operands.next().unwrap_or(Expr::from(&Value::Array(vec![])));
+                return operands.fold(first, |lhs, rhs| {
+                    Expr::new(ExprKind::Add(Box::new(lhs), Box::new(rhs)), Span::default())
+                });
+            }
+            Value::Function(f) => ExprKind::Call(f.name.clone(), f.args.iter().map(Expr::from).collect()),
+            Value::Select(s) => ExprKind::Select(SelectExpr {
+                conditions: s.conditions.clone(),
+                cases: s
+                    .cases
+                    .iter()
+                    .map(|c| Case {
+                        patterns: c.patterns.clone(),
+                        value: c.value.as_ref().map(Expr::from),
+                    })
+                    .collect(),
+            }),
+        };
+        Expr::new(kind, Span::default())
+    }
+}
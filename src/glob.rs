@@ -0,0 +1,356 @@
+//! expansion of the globs of path properties, like `srcs: ["src/**/*.cpp"]`
+//!
+//! Paths are relative to the directory of the blueprint file. As in soong,
+//! `**` matches any number of directories, globs do not descend into
+//! directories holding another `Android.bp`, and paths may not leave the
+//! directory with `..`. The files are read through a [`FileSystem`], which
+//! can be kept in memory:
+//!
+//! ```
+//! use android_bp::glob::MemoryFileSystem;
+//! use android_bp::BluePrint;
+//! use std::path::Path;
+//!
+//! let fs: MemoryFileSystem = ["foo/a.c", "foo/b.c", "foo/lib/c.c", "foo/sub/Android.bp", "foo/sub/d.c"]
+//!     .into_iter()
+//!     .collect();
+//! let bp = BluePrint::parse(r#"cc_library { srcs: ["**/*.c"], exclude_srcs: ["b.c"] }"#).unwrap();
+//! let srcs = bp.modules[0].paths("srcs", Path::new("foo"), &fs).unwrap();
+//! assert_eq!(srcs, ["a.c", "lib/c.c"]);
+//! ```
+
+use crate::error::Error;
+use crate::expr::ExprKind;
+use crate::parser::{Module, Value};
+use crate::span::Span;
+use std::collections::BTreeSet;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// the files globs are matched against
+pub trait FileSystem {
+    /// the names of the entries of a directory, with whether each one is a
+    /// directory. A missing directory has no entries.
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<(String, bool)>>;
+    /// the path is an existing file
+    fn is_file(&self, path: &Path) -> bool;
+}
+
+/// the files of the machine
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Default)]
+pub struct OsFileSystem;
+
+impl FileSystem for OsFileSystem {
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<(String, bool)>> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        entries
+            .map(|e| {
+                let e = e?;
+                Ok((e.file_name().to_string_lossy().into_owned(), e.path().is_dir()))
+            })
+            .collect()
+    }
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+}
+
+/// a tree of files kept in memory, given by their paths. Directories are the
+/// parents of the files.
+#[derive(Debug, PartialEq, Clone, Eq, Default)]
+pub struct MemoryFileSystem(pub BTreeSet<PathBuf>);
+
+impl<P: Into<PathBuf>> FromIterator<P> for MemoryFileSystem {
+    fn from_iter<I: IntoIterator<Item = P>>(files: I) -> Self {
+        MemoryFileSystem(files.into_iter().map(Into::into).collect())
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<(String, bool)>> {
+        let mut entries: Vec<(String, bool)> = Vec::new();
+        for file in &self.0 {
+            let Ok(rest) = file.strip_prefix(dir) else {
+                continue;
+            };
+            let mut components = rest.components();
+            let Some(name) = components.next() else {
+                continue;
+            };
+            let entry = (name.as_os_str().to_string_lossy().into_owned(), components.next().is_some());
+            if !entries.contains(&entry) {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+    fn is_file(&self, path: &Path) -> bool {
+        self.0.contains(path)
+    }
+}
+
+/// the path has glob characters
+fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// a `[a-z]` or `[^a-z]` class of a glob
+struct Class {
+    negated: bool,
+    ranges: Vec<(char, char)>,
+    /// the number of characters of the class in the pattern
+    len: usize,
+}
+
+/// the class at the start of a pattern, or `None` when it is malformed, like
+/// `[a-` or `[]`, which go's `filepath.Match` rejects as well
+fn parse_class(pattern: &[char]) -> Option<Class> {
+    let negated = matches!(pattern.get(1), Some('^' | '!'));
+    let mut i = if negated { 2 } else { 1 };
+    let mut ranges = Vec::new();
+    loop {
+        let lo = match *pattern.get(i)? {
+            ']' if !ranges.is_empty() => {
+                return Some(Class {
+                    negated,
+                    ranges,
+                    len: i + 1,
+                })
+            }
+            ']' | '-' => return None,
+            lo => lo,
+        };
+        let hi = match pattern.get(i + 1) {
+            Some('-') => match *pattern.get(i + 2)? {
+                ']' | '-' => return None,
+                hi => {
+                    i += 3;
+                    hi
+                }
+            },
+            _ => {
+                i += 1;
+                lo
+            }
+        };
+        ranges.push((lo, hi));
+    }
+}
+
+/// match a name against a path component of a glob: `*`, `?` and `[a-z]`,
+/// `[^a-z]` classes, as go's `filepath.Match`. Malformed classes never match,
+/// they are rejected by [`components`] first.
+fn match_name(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|i| match_name(&pattern[1..], &name[i..])),
+        Some('?') => !name.is_empty() && match_name(&pattern[1..], &name[1..]),
+        Some('[') => {
+            let (Some(c), Some(class)) = (name.first(), parse_class(pattern)) else {
+                return false;
+            };
+            let matched = class.ranges.iter().any(|(lo, hi)| (lo..=hi).contains(&c));
+            matched != class.negated && match_name(&pattern[class.len..], &name[1..])
+        }
+        Some(p) => name.first() == Some(p) && match_name(&pattern[1..], &name[1..]),
+    }
+}
+
+/// match a path against a glob, both split in components
+fn match_path(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| match_path(rest, &path[i..])),
+        Some((p, rest)) => match path.split_first() {
+            Some((name, path)) => {
+                let p: Vec<char> = p.chars().collect();
+                let name: Vec<char> = name.chars().collect();
+                match_name(&p, &name) && match_path(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+/// the components of a path relative to the module directory, rejecting
+/// absolute paths, `..`, misplaced `**` and malformed classes
+fn components(path: &str) -> Result<Vec<&str>, String> {
+    if path.starts_with('/') {
+        return Err(format!("path \"{}\" must be relative to the module directory", path));
+    }
+    let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty() && *c != ".").collect();
+    for c in &components {
+        if *c == ".." {
+            return Err(format!("path \"{}\" is outside the module directory", path));
+        }
+        if c.contains("**") && *c != "**" {
+            return Err(format!("\"**\" must be a full path component in \"{}\"", path));
+        }
+        let chars: Vec<char> = c.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            i += match chars[i] {
+                '[' => match parse_class(&chars[i..]) {
+                    Some(class) => class.len,
+                    None => return Err(format!("malformed character class in \"{}\"", path)),
+                },
+                _ => 1,
+            };
+        }
+    }
+    Ok(components)
+}
+
+struct Walker<'a, F: FileSystem + ?Sized> {
+    fs: &'a F,
+    root: &'a Path,
+}
+
+impl<F: FileSystem + ?Sized> Walker<'_, F> {
+    fn read_dir(&self, dir: &str) -> Result<Vec<(String, bool)>, Error> {
+        let path = self.root.join(dir);
+        let mut entries = self.fs.read_dir(&path).map_err(|error| Error::Io { file: path, error })?;
+        entries.sort();
+        Ok(entries)
+    }
+    /// the directory holds another blueprint file, globs do not descend into it
+    fn is_package(&self, dir: &str) -> bool {
+        self.fs.is_file(&self.root.join(dir).join("Android.bp"))
+    }
+    /// the files below `dir` matching the components of a glob
+    fn walk(&self, dir: &str, pattern: &[&str], files: &mut Vec<String>) -> Result<(), Error> {
+        let Some((first, rest)) = pattern.split_first() else {
+            return Ok(());
+        };
+        let join = |name: &str| if dir.is_empty() { name.to_string() } else { format!("{}/{}", dir, name) };
+        if *first == "**" {
+            self.walk(dir, rest, files)?;
+            for (name, is_dir) in self.read_dir(dir)? {
+                let sub = join(&name);
+                if is_dir && !self.is_package(&sub) {
+                    self.walk(&sub, pattern, files)?;
+                }
+            }
+            return Ok(());
+        }
+        let first: Vec<char> = first.chars().collect();
+        for (name, is_dir) in self.read_dir(dir)? {
+            if !match_name(&first, &name.chars().collect::<Vec<_>>()) {
+                continue;
+            }
+            let path = join(&name);
+            if rest.is_empty() {
+                if !is_dir {
+                    files.push(path);
+                }
+            } else if is_dir && !self.is_package(&path) {
+                self.walk(&path, rest, files)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// the files matching a glob, relative to the module directory `dir`, sorted.
+/// A path without glob characters is returned as is, when the file exists.
+/// Errors are reported at `span`, the location of the glob.
+fn glob<F: FileSystem + ?Sized>(fs: &F, dir: &Path, pattern: &str, span: Span) -> Result<Vec<String>, Error> {
+    let mut components = components(pattern).map_err(|e| Error::semantic(span, e))?;
+    if !is_glob(pattern) {
+        let path = components.join("/");
+        return match fs.is_file(&dir.join(&path)) {
+            true => Ok(vec![path]),
+            false => Err(Error::semantic(span, format!("source path \"{}\" does not exist", pattern))),
+        };
+    }
+    // `dir/**` matches every file below `dir`
+    if components.last() == Some(&"**") {
+        components.push("*");
+    }
+    let mut files = Vec::new();
+    Walker { fs, root: dir }.walk("", &components, &mut files)?;
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// the strings of a list property, each with its location
+pub(crate) fn strings_with_spans(module: &Module, key: &str) -> Result<Vec<(String, Span)>, Error> {
+    let span = module.value_span(key).unwrap_or(module.span());
+    let error = || Error::semantic(span, format!("\"{}\" must be a list of strings", key));
+    let values = match module.get(key) {
+        None => return Ok(Vec::new()),
+        Some(Value::Array(values)) => values,
+        Some(_) => return Err(error()),
+    };
+    let spans = match module.expr(key).map(|e| &e.kind) {
+        Some(ExprKind::List(l)) if l.len() == values.len() => l.iter().map(|e| e.span()).collect(),
+        _ => vec![span; values.len()],
+    };
+    values
+        .iter()
+        .zip(spans)
+        .map(|(v, span)| match v {
+            Value::String(s) => Ok((s.clone(), span)),
+            _ => Err(error()),
+        })
+        .collect()
+}
+
+impl Module {
+    /// the paths of a path property like `srcs`, relative to `dir`, the
+    /// directory of the blueprint file, with globs expanded, in order and
+    /// without duplicates.
+    ///
+    /// Files matching `exclude_<property>`, like `exclude_srcs`, or below the
+    /// directories of `exclude_dirs` are removed. References to other modules,
+    /// like `:libfoo_srcs`, are kept as they are, see [`Module::source_paths_with`]
+    /// to resolve them. Absolute paths and paths leaving `dir` are reported at
+    /// the path.
+    ///
+    /// As in soong, each path without glob characters must be an existing
+    /// file, even when it is excluded: a single missing file fails the whole
+    /// property, and is reported at its path.
+    /// The module should be resolved for a variant first.
+    pub fn paths<F: FileSystem + ?Sized>(&self, property: &str, dir: &Path, fs: &F) -> Result<Vec<String>, Error> {
+        Ok(self.paths_with_spans(property, dir, fs)?.into_iter().map(|(p, _)| p).collect())
+    }
+    /// see [`Module::paths`], each path with the location of the value it comes from
+    pub(crate) fn paths_with_spans<F: FileSystem + ?Sized>(
+        &self,
+        property: &str,
+        dir: &Path,
+        fs: &F,
+    ) -> Result<Vec<(String, Span)>, Error> {
+        let mut excludes = Vec::new();
+        for (pattern, span) in strings_with_spans(self, &format!("exclude_{}", property))? {
+            excludes.push(components(&pattern).map_err(|e| Error::semantic(span, e))?.join("/"));
+        }
+        for (pattern, span) in strings_with_spans(self, "exclude_dirs")? {
+            let dir = components(&pattern).map_err(|e| Error::semantic(span, e))?.join("/");
+            excludes.push(format!("{}/**", dir));
+        }
+        let excludes: Vec<Vec<&str>> = excludes.iter().map(|e| e.split('/').collect()).collect();
+        let excluded = |path: &str| {
+            let path: Vec<&str> = path.split('/').collect();
+            excludes.iter().any(|e| match_path(e, &path))
+        };
+        let mut paths: Vec<(String, Span)> = Vec::new();
+        for (pattern, span) in strings_with_spans(self, property)? {
+            if pattern.starts_with(':') {
+                paths.push((pattern, span));
+                continue;
+            }
+            for file in glob(fs, dir, &pattern, span)? {
+                if !excluded(&file) && !paths.iter().any(|(p, _)| *p == file) {
+                    paths.push((file, span));
+                }
+            }
+        }
+        Ok(paths)
+    }
+}
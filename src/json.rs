@@ -0,0 +1,210 @@
+//! minimal JSON support, used for machine readable diagnostics and to read
+//! the `soong.variables` of a product
+
+use crate::utils::VerboseResult;
+use nom::{
+    branch::alt,
+    bytes::complete::{is_not, tag, take_while_m_n},
+    character::complete::{char, digit1, multispace0},
+    combinator::{cut, eof, map, map_opt, map_res, opt, recognize, value, verify},
+    error::context,
+    multi::{many0, separated_list0},
+    sequence::{delimited, pair, preceded, separated_pair, terminated},
+};
+use std::fmt;
+
+/// a JSON value
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    String(String),
+    Array(Vec<Json>),
+    /// an object, keeping the order of its keys
+    Object(Vec<(String, Json)>),
+}
+impl Json {
+    /// build an object from its entries
+    pub(crate) fn object<'a>(entries: impl IntoIterator<Item = (&'a str, Json)>) -> Json {
+        Json::Object(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+        )
+    }
+}
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Json::String(s.to_string())
+    }
+}
+impl From<usize> for Json {
+    fn from(i: usize) -> Self {
+        Json::Integer(i as i64)
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Integer(i) => write!(f, "{}", i),
+            Json::String(s) => write_string(f, s),
+            Json::Array(a) => {
+                f.write_str("[")?;
+                for (i, v) in a.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                f.write_str("]")
+            }
+            Json::Object(o) => {
+                f.write_str("{")?;
+                for (i, (k, v)) in o.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, k)?;
+                    write!(f, ":{}", v)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+/// a `\uXXXX` escape, combining surrogate pairs
+fn parse_unicode(input: &str) -> VerboseResult<'_, char> {
+    let hex = |i| {
+        preceded(
+            tag("\\u"),
+            map_res(take_while_m_n(4, 4, |c: char| c.is_ascii_hexdigit()), |h| {
+                u32::from_str_radix(h, 16)
+            }),
+        )(i)
+    };
+    alt((
+        map_opt(
+            pair(
+                verify(hex, |h| (0xd800..0xdc00).contains(h)),
+                verify(hex, |l| (0xdc00..0xe000).contains(l)),
+            ),
+            |(h, l)| char::from_u32(0x10000 + ((h - 0xd800) << 10) + (l - 0xdc00)),
+        ),
+        map_opt(hex, char::from_u32),
+    ))(input)
+}
+
+fn parse_string(input: &str) -> VerboseResult<'_, String> {
+    let escaped = preceded(
+        char('\\'),
+        alt((
+            value('"', char('"')),
+            value('\\', char('\\')),
+            value('/', char('/')),
+            value('\u{08}', char('b')),
+            value('\u{0C}', char('f')),
+            value('\n', char('n')),
+            value('\r', char('r')),
+            value('\t', char('t')),
+        )),
+    );
+    let fragment = alt((
+        map(is_not("\"\\"), str::to_string),
+        map(parse_unicode, String::from),
+        map(escaped, String::from),
+    ));
+    context(
+        "string",
+        delimited(char('"'), map(many0(fragment), |f| f.concat()), cut(char('"'))),
+    )(input)
+}
+
+fn parse_value(input: &str) -> VerboseResult<'_, Json> {
+    let integer = map_res(recognize(pair(opt(char('-')), digit1)), |i: &str| i.parse());
+    let array = delimited(
+        char('['),
+        separated_list0(char(','), parse_value),
+        cut(preceded(multispace0, char(']'))),
+    );
+    let member = separated_pair(
+        preceded(multispace0, parse_string),
+        cut(preceded(multispace0, char(':'))),
+        parse_value,
+    );
+    let object = delimited(
+        char('{'),
+        separated_list0(char(','), member),
+        cut(preceded(multispace0, char('}'))),
+    );
+    context(
+        "value",
+        delimited(
+            multispace0,
+            alt((
+                value(Json::Null, tag("null")),
+                value(Json::Bool(true), tag("true")),
+                value(Json::Bool(false), tag("false")),
+                map(integer, Json::Integer),
+                map(parse_string, Json::String),
+                map(array, Json::Array),
+                map(object, Json::Object),
+            )),
+            multispace0,
+        ),
+    )(input)
+}
+
+/// parse a JSON document. Numbers must be integers.
+pub(crate) fn parse(input: &str) -> Result<Json, crate::Error> {
+    let (_, json) = terminated(parse_value, cut(eof))(input).map_err(|e| crate::Error::syntax(input, e))?;
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_display() {
+        let value = Json::object([
+            ("a", Json::Integer(-1)),
+            ("b", Json::Array(vec!["x\"\n\u{1}".into(), Json::Array(vec![])])),
+        ]);
+        assert_eq!(value.to_string(), r#"{"a":-1,"b":["x\"\n\u0001",[]]}"#);
+    }
+    #[test]
+    fn test_json_parse() {
+        let input = r#" {"a": [1, -2, true, null], "b\u00e9\ud83d\ude00": {"c": "\"\n"}, "d": {}} "#;
+        let expected = Json::object([
+            ("a", Json::Array(vec![Json::Integer(1), Json::Integer(-2), Json::Bool(true), Json::Null])),
+            ("bé😀", Json::object([("c", "\"\n".into())])),
+            ("d", Json::Object(vec![])),
+        ]);
+        assert_eq!(parse(input).unwrap(), expected);
+        assert_eq!(parse(&expected.to_string()).unwrap(), expected);
+        assert!(parse("[1,").is_err());
+        assert!(parse("{} {}").is_err());
+    }
+}
@@ -8,8 +8,43 @@ mod macros;
 mod utils;
 mod tests;
 mod string;
+mod span;
+mod error;
+mod json;
+mod defaults;
+mod variant;
+mod product;
+mod select;
+mod reference;
+pub mod diagnostic;
+pub mod cst;
+pub mod expr;
+pub mod soong_config;
+pub mod glob;
 
 pub use parser::BluePrint;
 pub use parser::Value;
 pub use parser::Module;
 pub use parser::Map;
+pub use parser::Statement;
+pub use parser::Assignment;
+pub use parser::AssignOp;
+pub use parser::Select;
+pub use parser::SelectCase;
+pub use parser::SelectPattern;
+pub use parser::Condition;
+pub use expr::Expr;
+pub use variant::Variant;
+pub use variant::Linkage;
+pub use soong_config::SoongConfig;
+pub use product::ProductVariables;
+pub use select::Configuration;
+pub use reference::ModuleReference;
+pub use reference::SourcePath;
+pub use span::Position;
+pub use span::Span;
+pub use span::EntrySpan;
+pub use error::Error;
+pub use diagnostic::Diagnostic;
+pub use diagnostic::Severity;
+pub use indexmap::IndexMap;
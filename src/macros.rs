@@ -11,6 +11,7 @@ macro_rules! context_tag {
     };
 }
 // define macro ending delimiter with optional comma
+// trailing spaces are left to the caller, so that spans end on the delimiter
 #[macro_export(local_inner_macros)]
 #[doc(hidden)]
 macro_rules! end_delimiter {
@@ -19,8 +20,7 @@ macro_rules! end_delimiter {
             space_or_comments,
             opt(char(',')),
             space_or_comments,
-            cut(tag($tag)),
-            space_or_comments,
+            cut(context($tag, tag($tag))),
         ))
     };
 }
@@ -1,25 +1,27 @@
-use crate::string::parse_string;
+use crate::error::Error;
+use crate::expr::{Case, Expr, ExprKind, Property, SelectExpr};
+use crate::span::{EntrySpan, LineIndex, SourceInfo, Span};
+use crate::string::{parse_raw_string, parse_string};
 use crate::{context_tag, end_delimiter, utils::*};
-use nom::combinator::map_res;
-use nom::Err;
 use nom::{
     branch::alt,
     bytes::complete::tag,
     character::complete::char,
-    combinator::{cut, map, opt},
-    error::{context, convert_error, VerboseError},
-    multi::{many0, separated_list0},
-    sequence::{delimited, tuple},
+    combinator::{cut, map, map_res, opt, verify},
+    error::context,
+    multi::{separated_list0, separated_list1},
+    sequence::{delimited, preceded, terminated, tuple},
 };
-use std::collections::HashMap;
+use indexmap::map::Entry;
+use indexmap::IndexMap;
 use std::ops::{Deref, DerefMut};
 use std::path::Path;
 
-/// a dictionary in a blueprint file
-#[derive(Debug, PartialEq, Clone, Eq)]
-pub struct Map(pub HashMap<String, Value>);
+/// a dictionary in a blueprint file, iterated in source order
+#[derive(Debug, PartialEq, Clone, Eq, Default)]
+pub struct Map(pub IndexMap<String, Value>, pub(crate) SourceInfo<Box<Spans>>);
 impl Deref for Map {
-    type Target = HashMap<String, Value>;
+    type Target = IndexMap<String, Value>;
     fn deref(&self) -> &Self::Target {
         &self.0
     }
@@ -29,16 +31,136 @@ impl DerefMut for Map {
         &mut self.0
     }
 }
-fn parse_dict(input: &str) -> VerboseResult<Map> {
+impl From<IndexMap<String, Value>> for Map {
+    fn from(entries: IndexMap<String, Value>) -> Self {
+        Map(entries, Default::default())
+    }
+}
+impl Map {
+    /// get the span of the map, from its opening to its closing brace
+    pub fn span(&self) -> Span {
+        self.1.span
+    }
+    /// get the span of the key of an entry
+    pub fn key_span(&self, key: &str) -> Option<Span> {
+        self.1.entries.get(key).map(|e| e.key)
+    }
+    /// get the span of the value of an entry
+    pub fn value_span(&self, key: &str) -> Option<Span> {
+        self.1.entries.get(key).map(|e| e.value)
+    }
+    /// entries whose key was already defined, which only
+    /// [`BluePrint::parse_lenient`] accepts
+    pub fn duplicates(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.1.duplicates.iter().map(|(k, v, _)| (k.as_str(), v))
+    }
+    /// merge the entries of `other`, combining the values of the keys found in
+    /// both maps with `f`
+    pub(crate) fn merge_with(
+        &mut self,
+        other: &Map,
+        f: fn(Value, &Value) -> Result<Value, String>,
+    ) -> Result<(), String> {
+        for (k, v) in other.iter() {
+            match self.0.get_mut(k) {
+                Some(prev) => {
+                    let value = std::mem::replace(prev, Value::Boolean(false));
+                    *prev = f(value, v)?;
+                }
+                None => {
+                    if let Some(span) = other.1.entries.get(k) {
+                        self.1.entries.insert(k.clone(), *span);
+                    }
+                    self.0.insert(k.clone(), v.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// locations of a module or a map, and of its entries
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Spans {
+    /// the whole item, up to its closing delimiter
+    pub span: Span,
+    /// the closing delimiter
+    pub close: Span,
+    pub entries: IndexMap<String, EntrySpan>,
+    /// entries whose key was already defined, see [`BluePrint::parse_lenient`]
+    pub duplicates: Vec<(String, Value, EntrySpan)>,
+}
+impl Spans {
+    /// build the spans of an item, without entries.
+    /// `span` is what the item consumed, ending with its one-byte closing delimiter.
+    fn new(span: Span) -> Self {
+        let mut close = span;
+        close.start.offset = span.end.offset + 1;
+        Spans {
+            span,
+            close,
+            ..Default::default()
+        }
+    }
+    fn resolve(&mut self, index: &LineIndex) {
+        index.resolve(&mut self.span);
+        index.resolve(&mut self.close);
+        self.entries.values_mut().for_each(|e| index.resolve_entry(e));
+        for (_, v, e) in self.duplicates.iter_mut() {
+            v.resolve_spans(index);
+            index.resolve_entry(e);
+        }
+    }
+    /// report the keys defined twice in the item, then in the maps nested in its values
+    fn check_duplicates(&self, prefix: &str, entries: &IndexMap<String, Value>, errors: &mut Vec<Error>) {
+        for (k, _, span) in &self.duplicates {
+            errors.push(
+                Error::semantic(span.key, format!("property \"{}{}\" already defined", prefix, k))
+                    .with_note(self.entries[k].key, "previous definition here"),
+            );
+        }
+        for (k, v) in entries {
+            v.check_duplicates(&format!("{}{}.", prefix, k), errors);
+        }
+    }
+}
+/// build the entries of an item, keeping the first definition of each key
+pub(crate) fn entries_with_spans(
+    (entries, span): (Vec<(String, Value, EntrySpan)>, Span),
+) -> (IndexMap<String, Value>, Spans) {
+    let mut spans = Spans::new(span);
+    let mut map = IndexMap::new();
+    for (k, v, entry_span) in entries {
+        match map.entry(k) {
+            Entry::Occupied(e) => spans.duplicates.push((e.key().clone(), v, entry_span)),
+            Entry::Vacant(e) => {
+                spans.entries.insert(e.key().clone(), entry_span);
+                e.insert(v);
+            }
+        }
+    }
+    (map, spans)
+}
+fn parse_dict(input: &str) -> VerboseResult<'_, Vec<Property>> {
+    let (input, _) = space_or_comments(input)?;
     context(
         "dict",
         map(
             delimited(
-                tuple((space_or_comments, context_tag!("{"), space_or_comments)),
-                separated_list0(char(','), parse_module_entry),
+                tuple((context_tag!("{"), space_or_comments)),
+                separated_list0(char(','), |i| parse_module_entry(i, ':')),
                 end_delimiter!("}"),
             ),
-            |entries| Map(entries.into_iter().collect()),
+            |entries| {
+                entries
+                    .into_iter()
+                    .map(|(name, value, span)| Property {
+                        name,
+                        value,
+                        name_span: SourceInfo(span.key),
+                    })
+                    .collect()
+            },
         ),
     )(input)
 }
@@ -47,7 +169,7 @@ pub struct Function {
     pub name: String,
     pub args: Vec<Value>,
 }
-fn parse_function(input: &str) -> VerboseResult<Function> {
+fn parse_function(input: &str) -> VerboseResult<'_, (String, Vec<Expr>)> {
     context(
         "function",
         map(
@@ -61,13 +183,153 @@ fn parse_function(input: &str) -> VerboseResult<Function> {
                     end_delimiter!(")"),
                 ),
             )),
-            |(_, name, _, args)| Function {
+            |(_, name, _, args)| (name.to_string(), args),
+        ),
+    )(input)
+}
+/// a condition of a select: `arch()`, `soong_config_variable("ns", "var")`...
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Condition {
+    pub name: String,
+    pub args: Vec<String>,
+}
+/// a pattern of a select case
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub enum SelectPattern {
+    String(String),
+    Boolean(bool),
+    /// `default`: matches anything, even an unset condition
+    Default,
+    /// `any`, or `any @ binding`: matches any set condition
+    Any(Option<String>),
+}
+/// a case of a select: `("arm64", default): value`
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct SelectCase {
+    /// one pattern per condition, or a single `default`
+    pub patterns: Vec<SelectPattern>,
+    /// the value of the case, `None` for `unset`
+    pub value: Option<Value>,
+}
+/// a `select(conditions, { cases })` expression
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Select {
+    pub conditions: Vec<Condition>,
+    /// cases, in the order they were written
+    pub cases: Vec<SelectCase>,
+}
+/// a keyword, which must not be followed by other identifier characters
+fn keyword<'a>(k: &'static str) -> impl FnMut(&'a str) -> VerboseResult<'a, &'a str> {
+    verify(identifier, move |i: &str| i == k)
+}
+fn parse_condition(input: &str) -> VerboseResult<'_, Condition> {
+    context(
+        "condition",
+        map(
+            tuple((
+                ws(identifier),
+                delimited(
+                    context_tag!("("),
+                    separated_list0(comma, ws(string_literal)),
+                    end_delimiter!(")"),
+                ),
+            )),
+            |(name, args)| Condition {
                 name: name.to_string(),
                 args,
             },
         ),
     )(input)
 }
+fn parse_select_pattern(input: &str) -> VerboseResult<'_, SelectPattern> {
+    context(
+        "pattern",
+        ws(alt((
+            map(string_literal, SelectPattern::String),
+            map(keyword("default"), |_| SelectPattern::Default),
+            map(
+                preceded(
+                    keyword("any"),
+                    opt(preceded(ws(char('@')), cut(identifier))),
+                ),
+                |binding| SelectPattern::Any(binding.map(|b| b.to_string())),
+            ),
+            map(parse_bool, SelectPattern::Boolean),
+        ))),
+    )(input)
+}
+fn parse_select_case(input: &str) -> VerboseResult<'_, Case> {
+    context(
+        "select case",
+        map(
+            tuple((
+                space_or_comments,
+                alt((
+                    delimited(
+                        char('('),
+                        separated_list0(comma, parse_select_pattern),
+                        end_delimiter!(")"),
+                    ),
+                    map(parse_select_pattern, |p| vec![p]),
+                )),
+                ws(char(':')),
+                cut(alt((
+                    map(keyword("unset"), |_| None),
+                    map(parse_expr, Some),
+                ))),
+                space_or_comments,
+            )),
+            |(_, patterns, _, value, _)| Case { patterns, value },
+        ),
+    )(input)
+}
+/// a case of a select on `conditions` conditions, with one pattern per
+/// condition or a single `default`. A select has one `default` case at most,
+/// `has_default` tells whether it was already found.
+fn parse_checked_case<'a>(input: &'a str, conditions: usize, has_default: &mut bool) -> VerboseResult<'a, Case> {
+    let (input, _) = space_or_comments(input)?;
+    let (rest, case) = parse_select_case(input)?;
+    match case.patterns.as_slice() {
+        [SelectPattern::Default] if *has_default => return invalid(input, "select with a single default case"),
+        [SelectPattern::Default] => *has_default = true,
+        patterns if patterns.len() != conditions => {
+            return invalid(input, "select case with one pattern per condition");
+        }
+        _ => {}
+    }
+    Ok((rest, case))
+}
+fn parse_select(input: &str) -> VerboseResult<'_, SelectExpr> {
+    let (input, _) = tuple((space_or_comments, keyword("select"), context_tag!("(")))(input)?;
+    let (input, conditions) = context(
+        "select",
+        cut(terminated(
+            alt((
+                delimited(
+                    context_tag!("("),
+                    separated_list1(comma, parse_condition),
+                    end_delimiter!(")"),
+                ),
+                map(parse_condition, |c| vec![c]),
+            )),
+            comma,
+        )),
+    )(input)?;
+    let count = conditions.len();
+    let mut has_default = false;
+    let (input, cases) = context(
+        "select",
+        cut(terminated(
+            delimited(
+                context_tag!("{"),
+                separated_list0(char(','), |i| parse_checked_case(i, count, &mut has_default)),
+                end_delimiter!("}"),
+            ),
+            end_delimiter!(")"),
+        )),
+    )(input)?;
+    Ok((input, SelectExpr { conditions, cases }))
+}
 /// a value in a blueprint file
 #[derive(Debug, PartialEq, Clone, Eq)]
 pub enum Value {
@@ -79,6 +341,7 @@ pub enum Value {
     Ident(String),
     ConcatExpr(Vec<Value>),
     Function(Function),
+    Select(Select),
 }
 // convert value from str
 impl From<&str> for Value {
@@ -86,44 +349,54 @@ impl From<&str> for Value {
         Value::String(s.to_string())
     }
 }
-fn parse_value(input: &str) -> VerboseResult<Value> {
+fn parse_value(input: &str) -> VerboseResult<'_, Expr> {
+    let (input, _) = space_or_comments(input)?;
     context(
         "value",
-        alt((
-            map(parse_array, Value::Array),
-            map(parse_function, Value::Function),
-            map(string_literal, Value::String),
-            map(parse_bool, Value::Boolean),
-            map(parse_dict, Value::Map),
-            map(parse_int, Value::Integer),
-            map(identifier, |x| Value::Ident(x.to_string())),
-        )),
+        map(
+            spanned(alt((
+                map(parse_array, ExprKind::List),
+                map(parse_select, ExprKind::Select),
+                map(parse_function, |(name, args)| ExprKind::Call(name, args)),
+                context(
+                    "string",
+                    alt((
+                        map(parse_string, |value| ExprKind::String { value, raw: false }),
+                        map(parse_raw_string, |value| ExprKind::String { value, raw: true }),
+                    )),
+                ),
+                map(parse_bool, ExprKind::Boolean),
+                map(parse_dict, ExprKind::Map),
+                map(parse_int, ExprKind::Integer),
+                map(identifier, |x| ExprKind::Variable(x.to_string())),
+            ))),
+            |(kind, span)| Expr::new(kind, span),
+        ),
     )(input)
 }
-fn concat_value_string(values: Vec<Value>) -> Result<Value, &'static str> {
-    let mut result = String::new();
-    for value in values {
-        match value {
-            Value::String(s) => result.push_str(&s),
-            _ => Err("value is not a string")?,
-        }
-    }
-    Ok(Value::String(result))
+/// `values[0] + values[1] + ...`
+fn add_values(values: Vec<Value>) -> Result<Value, String> {
+    let mut values = values.into_iter();
+    let first = values.next().ok_or("no value")?;
+    values.try_fold(first, Value::plus)
 }
-fn concat_value_array(values: Vec<Value>) -> Result<Value, &'static str> {
-    let mut result = Vec::new();
-    for value in values {
-        match value {
-            Value::Array(a) => result.extend(a),
-            _ => Err("value is not an array")?,
-        }
+/// the value of `values[0] + values[1] + ...`, or a `ConcatExpr` when it
+/// depends on variables
+pub(crate) fn fold_values(values: Vec<Value>) -> Value {
+    if values.len() == 1 {
+        return values.into_iter().next().unwrap();
     }
-    Ok(Value::Array(result))
+    // if there is one ident we cannot concat
+    if values.iter().any(Value::is_unresolved) {
+        return Value::ConcatExpr(values);
+    }
+    // mismatched types are reported once the whole file is parsed,
+    // see Value::operator_error
+    add_values(values.clone()).unwrap_or(Value::ConcatExpr(values))
 }
-pub(crate) fn parse_expr(input: &str) -> VerboseResult<Value> {
-    // in bp, value can be combined with '+' operator
-    // this parser parse the expression and combine the values
-    // into a single value, if there is no Ident in the values
+/// parse an expression, values combined with `+`
+pub(crate) fn parse_expr(input: &str) -> VerboseResult<'_, Expr> {
+    // in bp, values can be combined with the '+' operator, from left to right
     context(
         "expr",
         map_res(
@@ -131,30 +404,22 @@ pub(crate) fn parse_expr(input: &str) -> VerboseResult<Value> {
                 tuple((space_or_comments, char('+'), space_or_comments)),
                 parse_value,
             ),
-            |values| {
-                match values.len() {
-                    0 => Err("no value"),
-                    1 => Ok(values[0].clone()),
-                    _ => {
-                        // if there is one ident we cannot concat
-                        if values
-                            .iter()
-                            .any(|v| matches!(v, Value::Ident(_) | Value::Function(_)))
-                        {
-                            return Ok(Value::ConcatExpr(values));
-                        }
-                        match &values[0] {
-                            Value::String(_) => concat_value_string(values),
-                            Value::Array(_) => concat_value_array(values),
-                            _ => Err("first value is not a string"),
-                        }
-                    }
-                }
+            |operands| {
+                let mut operands = operands.into_iter();
+                let first = operands.next().ok_or("no value")?;
+                Ok::<_, &str>(operands.fold(first, |lhs, rhs| {
+                    let span = Span {
+                        start: lhs.span().start,
+                        end: rhs.span().end,
+                    };
+                    Expr::new(ExprKind::Add(Box::new(lhs), Box::new(rhs)), span)
+                }))
             },
         ),
     )(input)
 }
-pub(crate) fn parse_array(input: &str) -> VerboseResult<Vec<Value>> {
+/// parse an array, `[a, b]`
+pub(crate) fn parse_array(input: &str) -> VerboseResult<'_, Vec<Expr>> {
     context(
         "array",
         delimited(
@@ -168,24 +433,52 @@ pub(crate) fn parse_array(input: &str) -> VerboseResult<Vec<Value>> {
 /// a blueprint file
 #[derive(Debug, PartialEq, Clone, Eq)]
 pub struct BluePrint {
-    /// variables in the blueprint file
+    /// variables in the blueprint file, in definition order
     /// found in root of the file in the form of `key = value`
-    pub variables: HashMap<String, Value>,
+    pub variables: IndexMap<String, Value>,
     /// all ordered modules in the blueprint file
     pub modules: Vec<Module>,
+    /// the modules and the variable assignments, in source order,
+    /// with `+=` kept as written
+    pub statements: Vec<Statement>,
+    /// locations of the `=` and `+=` definitions of each variable
+    pub(crate) variable_spans: SourceInfo<IndexMap<String, Vec<EntrySpan>>>,
 }
 
 /// a module in a blueprint file
-#[derive(Debug, PartialEq, Clone, Eq)]
+#[derive(Debug, PartialEq, Clone, Eq, Default)]
 pub struct Module {
     pub typ: String,
-    pub entries: HashMap<String, Value>,
+    /// properties of the module, in source order, see [`Module::entries`]
+    pub(crate) entries: IndexMap<String, Value>,
+    pub(crate) spans: SourceInfo<Spans>,
+    /// properties as written, from which `entries` were computed
+    pub(crate) exprs: SourceInfo<IndexMap<String, Expr>>,
 }
 impl Module {
+    /// create a module, without location information
+    pub fn new(typ: &str, entries: IndexMap<String, Value>) -> Self {
+        let exprs = entries.iter().map(|(k, v)| (k.clone(), v.into())).collect();
+        Module {
+            typ: typ.to_string(),
+            entries,
+            exprs: SourceInfo(exprs),
+            ..Default::default()
+        }
+    }
+    /// properties of the module, in source order. They are modified with
+    /// [`Module::set`] and [`Module::remove`], which keep their expressions in line.
+    pub fn entries(&self) -> &IndexMap<String, Value> {
+        &self.entries
+    }
     /// get an attribute value from a module
     pub fn get(&self, key: &str) -> Option<&Value> {
         self.entries.get(key)
     }
+    /// get an attribute from a module, as written in the file
+    pub fn expr(&self, key: &str) -> Option<&Expr> {
+        self.exprs.get(key)
+    }
     /// get a string attribute value from a module
     pub fn get_string(&self, key: &str) -> Option<&String> {
         match self.get(key) {
@@ -228,184 +521,619 @@ impl Module {
             _ => None,
         }
     }
+    /// get the span of the module, from its type to its closing brace
+    pub fn span(&self) -> Span {
+        self.spans.span
+    }
+    /// get the span of the module type token
+    pub fn type_span(&self) -> Span {
+        let mut span = self.spans.span;
+        span.end = span.start;
+        span.end.offset += self.typ.len();
+        span.end.column += self.typ.chars().count();
+        span
+    }
+    /// get the span of the closing brace (or parenthesis) of the module
+    pub fn close_span(&self) -> Span {
+        self.spans.close
+    }
+    /// get the span of the key of an attribute
+    pub fn key_span(&self, key: &str) -> Option<Span> {
+        self.spans.entries.get(key).map(|e| e.key)
+    }
+    /// get the span of the value of an attribute
+    pub fn value_span(&self, key: &str) -> Option<Span> {
+        self.spans.entries.get(key).map(|e| e.value)
+    }
+    /// entries whose key was already defined, which only
+    /// [`BluePrint::parse_lenient`] accepts
+    pub fn duplicates(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.spans.duplicates.iter().map(|(k, v, _)| (k.as_str(), v))
+    }
+    /// set a property. Its expression, see [`Module::expr`], is computed from
+    /// the value, so that [`Module::resolved`] keeps it.
+    pub fn set(&mut self, key: &str, value: Value) {
+        self.exprs.insert(key.to_string(), (&value).into());
+        self.entries.insert(key.to_string(), value);
+    }
+    /// remove a property, with its expression and its location
+    pub fn remove(&mut self, key: &str) {
+        self.entries.shift_remove(key);
+        self.exprs.shift_remove(key);
+        self.spans.entries.shift_remove(key);
+    }
+    /// evaluate the properties of the module with `variables`, see [`Expr::evaluate`].
+    /// The typed getters then return the values of properties built from expressions.
+    pub fn resolved(&self, variables: &IndexMap<String, Value>) -> Result<Module, Error> {
+        let entries = self
+            .exprs
+            .iter()
+            .map(|(k, e)| Ok((k.clone(), e.evaluate(variables)?)))
+            .collect::<Result<_, Error>>()?;
+        Ok(Module {
+            typ: self.typ.clone(),
+            entries,
+            spans: self.spans.clone(),
+            exprs: self.exprs.clone(),
+        })
+    }
+    fn resolve_spans(&mut self, index: &LineIndex) {
+        self.spans.resolve(index);
+        self.entries.values_mut().for_each(|v| v.resolve_spans(index));
+        self.exprs.values_mut().for_each(|e| e.resolve_spans(index));
+    }
 }
-/// parse a module entry, with `:` as delimiter
-pub(crate) fn parse_module_entry(input: &str) -> VerboseResult<(String, Value)> {
-    _parse_module_entry(input, ':')
-}
-/// second form of module entry, with `=` as delimiter
-pub(crate) fn parse_module_entry2(input: &str) -> VerboseResult<(String, Value)> {
-    _parse_module_entry(input, '=')
+impl Value {
+    /// name of the type of the value, as blueprint calls it
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "int64",
+            Value::Array(_) => "list",
+            Value::Boolean(_) => "bool",
+            Value::Map(_) => "map",
+            Value::Ident(_) => "variable",
+            Value::ConcatExpr(_) => "expression",
+            Value::Function(_) => "function",
+            Value::Select(_) => "select",
+        }
+    }
+    /// the value depends on variables or on the configuration
+    fn is_unresolved(&self) -> bool {
+        matches!(
+            self,
+            Value::Ident(_) | Value::ConcatExpr(_) | Value::Function(_) | Value::Select(_)
+        )
+    }
+    /// evaluate `self + rhs` with blueprint rules: strings and lists are
+    /// concatenated, integers are added, and maps are merged, adding the values
+    /// of the keys found in both maps.
+    /// Values that cannot be evaluated yet are kept in a `ConcatExpr`.
+    pub fn plus(self, rhs: Value) -> Result<Value, String> {
+        Ok(match (self, rhs) {
+            (Value::String(mut a), Value::String(b)) => {
+                a.push_str(&b);
+                Value::String(a)
+            }
+            (Value::Integer(a), Value::Integer(b)) => Value::Integer(
+                a.checked_add(b)
+                    .ok_or_else(|| format!("integer overflow in operator +: {} + {}", a, b))?,
+            ),
+            (Value::Array(mut a), Value::Array(b)) => {
+                a.extend(b);
+                Value::Array(a)
+            }
+            (Value::Map(mut a), Value::Map(b)) => {
+                for (k, v) in b.0 {
+                    match a.0.get_mut(&k) {
+                        Some(prev) => {
+                            let value = std::mem::replace(prev, Value::Boolean(false));
+                            *prev = value.plus(v)?;
+                        }
+                        None => {
+                            if let Some(span) = b.1.entries.get(&k) {
+                                a.1.entries.insert(k.clone(), *span);
+                            }
+                            a.0.insert(k, v);
+                        }
+                    }
+                }
+                Value::Map(a)
+            }
+            (a, b) if a.is_unresolved() || b.is_unresolved() => {
+                let mut values = match a {
+                    Value::ConcatExpr(values) => values,
+                    a => vec![a],
+                };
+                match b {
+                    Value::ConcatExpr(b) => values.extend(b),
+                    b => values.push(b),
+                }
+                Value::ConcatExpr(values)
+            }
+            (Value::Boolean(_), Value::Boolean(_)) => {
+                Err("operator + is not supported on bool values")?
+            }
+            (a, b) => Err(format!(
+                "mismatched types in operator +: {} and {}",
+                a.type_name(),
+                b.type_name()
+            ))?,
+        })
+    }
+    /// error of the first `+` expression nested in this value that could not be
+    /// evaluated because of its operand types
+    fn operator_error(&self) -> Option<String> {
+        match self {
+            Value::ConcatExpr(values) if !values.iter().any(Value::is_unresolved) => {
+                add_values(values.clone()).err()
+            }
+            Value::Array(a) | Value::ConcatExpr(a) => a.iter().find_map(Value::operator_error),
+            Value::Map(m) => m.values().find_map(Value::operator_error),
+            Value::Function(f) => f.args.iter().find_map(Value::operator_error),
+            Value::Select(s) => s
+                .cases
+                .iter()
+                .filter_map(|c| c.value.as_ref())
+                .find_map(Value::operator_error),
+            _ => None,
+        }
+    }
+    /// report the keys defined twice in the maps nested in this value
+    fn check_duplicates(&self, prefix: &str, errors: &mut Vec<Error>) {
+        match self {
+            Value::Map(m) => m.1.check_duplicates(prefix, &m.0, errors),
+            Value::Array(a) | Value::ConcatExpr(a) => {
+                a.iter().for_each(|v| v.check_duplicates(prefix, errors))
+            }
+            Value::Function(f) => f.args.iter().for_each(|v| v.check_duplicates(prefix, errors)),
+            Value::Select(s) => s
+                .cases
+                .iter()
+                .filter_map(|c| c.value.as_ref())
+                .for_each(|v| v.check_duplicates(prefix, errors)),
+            _ => {}
+        }
+    }
+    /// resolve the spans of the maps nested in this value
+    fn resolve_spans(&mut self, index: &LineIndex) {
+        match self {
+            Value::Map(m) => {
+                m.1.resolve(index);
+                m.0.values_mut().for_each(|v| v.resolve_spans(index));
+            }
+            Value::Array(a) | Value::ConcatExpr(a) => {
+                a.iter_mut().for_each(|v| v.resolve_spans(index))
+            }
+            Value::Function(f) => f.args.iter_mut().for_each(|v| v.resolve_spans(index)),
+            Value::Select(s) => s
+                .cases
+                .iter_mut()
+                .filter_map(|c| c.value.as_mut())
+                .for_each(|v| v.resolve_spans(index)),
+            _ => {}
+        }
+    }
 }
-pub(crate) fn _parse_module_entry(input: &str, delimiter: char) -> VerboseResult<(String, Value)> {
+/// parse a module entry with its location.
+/// `delimiter` is `:` in modules and maps, and `=` in the second form of modules
+pub(crate) fn parse_module_entry(
+    input: &str,
+    delimiter: char,
+) -> VerboseResult<'_, (String, Expr, EntrySpan)> {
     context(
         "module entry",
         map(
             tuple((
                 space_or_comments,
-                alt((
+                spanned(alt((
                     map(identifier, |x| x.to_string()),
-                    parse_string::<VerboseError<&str>>,
-                )),
+                    string_literal,
+                ))),
                 space_or_comments,
                 char(delimiter),
                 space_or_comments,
                 cut(parse_expr),
                 space_or_comments,
             )),
-            |(_, key, _, _, _, value, _)| (key.to_string(), value),
+            |(_, (key, key_span), _, _, _, value, _)| {
+                let span = EntrySpan {
+                    key: key_span,
+                    value: value.span(),
+                };
+                (key, value, span)
+            },
         ),
     )(input)
 }
 
-pub(crate) fn parse_module(input: &str) -> VerboseResult<Module> {
+pub(crate) fn parse_module(input: &str) -> VerboseResult<'_, Module> {
     // parse a identifier followed by a module of entries
     let (input, _) = space_or_comments(input)?;
-    let (input, ident) = identifier(input)?;
-    let (input, _) = space_or_comments(input)?;
-    let (input, module) = context(
-        "module",
-        alt((
-            map(
+    let (rest, ((ident, properties), span)) = spanned(tuple((
+        identifier,
+        context(
+            "module",
+            alt((
                 delimited(
                     tuple((space_or_comments, context_tag!("{"), space_or_comments)),
-                    separated_list0(char(','), parse_module_entry),
+                    separated_list0(char(','), |i| parse_module_entry(i, ':')),
                     end_delimiter!("}"),
                 ),
-                |entries| entries.into_iter().collect(),
-            ),
-            map(
                 delimited(
                     tuple((space_or_comments, context_tag!("("), space_or_comments)),
-                    separated_list0(char(','), parse_module_entry2),
+                    separated_list0(char(','), |i| parse_module_entry(i, '=')),
                     end_delimiter!(")"),
                 ),
-                |entries| entries.into_iter().collect(),
-            ),
-        )),
-    )(input)?;
+            )),
+        ),
+    )))(input)?;
+    let (rest, _) = space_or_comments(rest)?;
+    let mut exprs = IndexMap::new();
+    let values = properties
+        .into_iter()
+        .map(|(k, e, entry_span)| {
+            let v = e.to_value();
+            exprs.entry(k.clone()).or_insert(e);
+            (k, v, entry_span)
+        })
+        .collect();
+    let (entries, spans) = entries_with_spans((values, span));
     Ok((
-        input,
+        rest,
         Module {
             typ: ident.to_string(),
-            entries: module,
+            entries,
+            spans: SourceInfo(spans),
+            exprs: SourceInfo(exprs),
         },
     ))
 }
 
-pub(crate) fn parse_define(input: &str) -> VerboseResult<(String, String, Value)> {
+pub(crate) fn parse_define(input: &str) -> VerboseResult<'_, Assignment> {
     context(
         "define",
         map(
             tuple((
                 space_or_comments,
-                identifier,
+                spanned(identifier),
                 space_or_comments,
-                alt((tag("="), tag("+="))),
+                alt((
+                    map(tag("="), |_| AssignOp::Assign),
+                    map(tag("+="), |_| AssignOp::Append),
+                )),
                 space_or_comments,
                 cut(parse_expr),
                 space_or_comments,
             )),
-            |(_, key, _, op, _, value, _)| (key.to_string(), op.to_string(), value),
+            |(_, (name, name_span), _, op, _, value, _)| Assignment {
+                name: name.to_string(),
+                op,
+                name_span: SourceInfo(name_span),
+                value,
+            },
         ),
     )(input)
 }
 
-pub(crate) fn parse_blueprint(input: &str) -> VerboseResult<BluePrint> {
-    let mut entries = Vec::new();
-    let mut variables = HashMap::new();
-    let (input, _) = context(
+/// the operator of a variable assignment
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum AssignOp {
+    /// `=`
+    Assign,
+    /// `+=`
+    Append,
+}
+/// a variable assignment: `name = value` or `name += value`
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Assignment {
+    pub name: String,
+    pub op: AssignOp,
+    /// the right-hand side, as written
+    pub value: Expr,
+    pub(crate) name_span: SourceInfo<Span>,
+}
+impl Assignment {
+    /// get the span of the variable name
+    pub fn name_span(&self) -> Span {
+        *self.name_span
+    }
+    /// get the span of the right-hand side
+    pub fn value_span(&self) -> Span {
+        self.value.span()
+    }
+}
+/// a top level statement of a blueprint file
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub enum Statement {
+    /// index of the module in [`BluePrint::modules`]
+    Module(usize),
+    Assignment(Assignment),
+}
+/// a parsed top level statement, before it is added to the blueprint
+pub(crate) enum Item {
+    Module(Module),
+    Assignment(Assignment),
+}
+/// parse the next top level statement, after any space or comment
+pub(crate) fn parse_statement(input: &str) -> VerboseResult<'_, Item> {
+    context(
         "blueprint",
-        many0(alt((
-            map(parse_module, |b| {
-                entries.push(b);
-                ()
-            }),
-            map_res(parse_define, |(k, op, v)| match op.as_str() {
-                "=" => {
-                    variables.insert(k, v);
-                    Ok(())
-                }
-                "+=" => {
-                    let e = variables.entry(k);
-                    match e {
-                        std::collections::hash_map::Entry::Occupied(prev) => {
-                            let prev = prev.into_mut();
-                            match prev {
-                                Value::String(s) => {
-                                    match v {
-                                        Value::String(s2) => {
-                                            s.push_str(&s2);
-                                        }
-                                        _ => Err("cannot append value to string")?,
-                                    }
-                                }
-                                Value::Array(a) => {
-                                    match v {
-                                        Value::Array(a2) => {
-                                            a.extend(a2);
-                                        }
-                                        Value::Ident(_) => {
-                                            Err("FIXME in this case, we should turn the Array into ConcatExpr")?
-                                        }
-                                        _ => Err("cannot append value to array")?,
-                                    }
-                                }
-                                Value::Integer(i) => {
-                                    match v {
-                                        Value::Integer(i2) => {
-                                            *i += i2;
-                                        }
-                                        _ => Err("cannot append value to integer")?,
-                                    }
-                                }
-                                _ => Err("cannot append value to this type")?,
-                            }
-                        }
-                        std::collections::hash_map::Entry::Vacant(_) => Err("variable not found")?,
-                    }
-                    Ok(())
-                }
-                _ => Err("unknown operator"),
-            }),
-            space_or_comments1,
-        ))),
-    )(input)?;
-    Ok((
-        input,
-        BluePrint {
-            variables: variables,
-            modules: entries,
-        },
-    ))
+        alt((
+            map(parse_module, Item::Module),
+            map(parse_define, Item::Assignment),
+        )),
+    )(input)
+}
+/// find where to resume parsing after a syntax error in the statement starting `input`:
+/// the next line starting with a module or a variable definition
+fn next_statement(input: &str) -> &str {
+    let mut starts_statement = tuple((
+        identifier,
+        space_or_comments,
+        alt((tag("{"), tag("("), tag("+="), tag("="))),
+    ));
+    input
+        .match_indices('\n')
+        .map(|(i, _)| &input[i + 1..])
+        .find(|line| starts_statement(line).is_ok())
+        .unwrap_or_default()
+}
+
+/// append a value to a variable defined with `+=`.
+/// Values that cannot be evaluated yet, like other variables, make a `ConcatExpr`
+fn append_variable(name: &str, prev: &mut Value, v: Value, span: Span) -> Result<(), Error> {
+    let same_type = std::mem::discriminant(prev) == std::mem::discriminant(&v);
+    if !(same_type || prev.is_unresolved() || v.is_unresolved()) || matches!(prev, Value::Boolean(_)) {
+        let message = match prev {
+            Value::String(_) => format!("cannot append value to string variable `{}`", name),
+            Value::Array(_) => format!("cannot append value to array variable `{}`", name),
+            Value::Integer(_) => format!("cannot append value to integer variable `{}`", name),
+            Value::Map(_) => format!("cannot append value to map variable `{}`", name),
+            _ => format!("cannot append value to variable `{}` of this type", name),
+        };
+        return Err(Error::semantic(span, message));
+    }
+    // merging maps can fail on the values of their common keys, keep the variable intact
+    let value = match prev {
+        Value::Map(_) => prev.clone(),
+        _ => std::mem::replace(prev, Value::Boolean(false)),
+    };
+    *prev = value.plus(v).map_err(|e| Error::semantic(span, e))?;
+    Ok(())
 }
 
-pub(crate) fn format_err(input: &str, err: Err<VerboseError<&str>>) -> String {
-    match err {
-        Err::Error(e) | Err::Failure(e) => convert_error(input, e.into()),
-        Err::Incomplete(_) => "Incomplete".to_string(),
+/// check an assignment against the variables defined before it, with blueprint
+/// rules: a variable is set once with `=`, and can only be extended with `+=`
+/// until it is referenced.
+/// `references` holds the first reference of each variable.
+fn check_assignment(
+    a: &Assignment,
+    defined: &IndexMap<String, Vec<EntrySpan>>,
+    references: &IndexMap<String, Span>,
+) -> Result<(), Error> {
+    let name = &a.name;
+    match (a.op, defined.get(name), references.get(name)) {
+        (AssignOp::Assign, Some(previous), _) => Err(Error::semantic(
+            a.name_span(),
+            format!("variable `{}` is already set", name),
+        )
+        .with_note(previous[0].key, "previous assignment here")),
+        (AssignOp::Append, None, _) => Err(Error::semantic(
+            a.name_span(),
+            format!("modified non-existent variable `{}` with +=", name),
+        )),
+        (AssignOp::Append, Some(_), Some(reference)) => Err(Error::semantic(
+            a.name_span(),
+            format!("modified variable `{}` with += after referencing it", name),
+        )
+        .with_note(*reference, "referenced here")),
+        _ => Ok(()),
     }
 }
+
+/// report a reference to a variable which is not set yet: as in blueprint,
+/// variables are defined before they are used
+fn check_reference(
+    name: &str,
+    span: Span,
+    defined: &IndexMap<String, Vec<EntrySpan>>,
+    errors: &mut Vec<Error>,
+) {
+    if !defined.contains_key(name) {
+        errors.push(Error::semantic(span, format!("variable `{}` is not set", name)));
+    }
+}
+
 impl BluePrint {
     /// parse an Android.bp file from a string
-    pub fn parse(input: &str) -> Result<Self, String> {
-        match parse_blueprint(input) {
-            Ok((rest, result)) => {
-                if rest.len() > 0 {
-                    return Err(format!("Unexpected left input: {}", rest));
+    pub fn parse(input: &str) -> Result<Self, Error> {
+        let (blueprint, errors) = Self::parse_recovering(input);
+        match errors.into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(blueprint),
+        }
+    }
+    /// parse an Android.bp file from a string, recovering from errors
+    ///
+    /// after a syntax error, parsing resumes at the next line starting a module
+    /// or a variable definition. All the errors are returned, ordered by
+    /// position, along with the modules and variables that could be parsed.
+    pub fn parse_recovering(input: &str) -> (Self, Vec<Error>) {
+        Self::parse_with(input, false)
+    }
+    /// parse an Android.bp file from a string, accepting keys defined twice in
+    /// a module or a map, which Soong rejects.
+    ///
+    /// The first definition is kept, the others are available with
+    /// [`Module::duplicates`] and [`Map::duplicates`], for lint tools.
+    pub fn parse_lenient(input: &str) -> Result<Self, Error> {
+        let (blueprint, errors) = Self::parse_with(input, true);
+        match errors.into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(blueprint),
+        }
+    }
+    fn parse_with(input: &str, lenient: bool) -> (Self, Vec<Error>) {
+        let mut errors = Vec::new();
+        let mut items = Vec::new();
+        // editors may start the file with a byte order mark. It is skipped,
+        // positions are still offsets in the input
+        let mut rest = input.strip_prefix('\u{feff}').unwrap_or(input);
+        loop {
+            // space_or_comments cannot fail
+            rest = space_or_comments(rest).map(|(r, _)| r).unwrap_or(rest);
+            if rest.is_empty() {
+                break;
+            }
+            match parse_statement(rest) {
+                Ok((r, item)) => {
+                    items.push(item);
+                    rest = r;
+                }
+                Err(e) => {
+                    errors.push(Error::syntax(input, e));
+                    rest = next_statement(rest);
+                }
+            }
+        }
+        let index = LineIndex::new(input);
+        let mut modules = Vec::new();
+        let mut statements = Vec::new();
+        let mut variables = IndexMap::new();
+        let mut variable_spans: IndexMap<String, Vec<EntrySpan>> = IndexMap::new();
+        let mut references = IndexMap::new();
+        for item in items {
+            match item {
+                Item::Module(mut m) => {
+                    m.resolve_spans(&index);
+                    if !lenient {
+                        m.spans.check_duplicates("", &m.entries, &mut errors);
+                    }
+                    for (k, v) in m.entries.iter() {
+                        if let Some(e) = v.operator_error() {
+                            errors.push(Error::semantic(m.spans.entries[k].value, e));
+                        }
+                    }
+                    for (name, span) in m.exprs.values().flat_map(Expr::references) {
+                        check_reference(name, span, &variable_spans, &mut errors);
+                        references.entry(name.to_string()).or_insert(span);
+                    }
+                    statements.push(Statement::Module(modules.len()));
+                    modules.push(m);
+                }
+                Item::Assignment(mut a) => {
+                    a.value.resolve_spans(&index);
+                    index.resolve(&mut a.name_span);
+                    let value = a.value.to_value();
+                    if !lenient {
+                        value.check_duplicates(&format!("{}.", a.name), &mut errors);
+                    }
+                    if let Some(e) = value.operator_error() {
+                        errors.push(Error::semantic(a.value_span(), e));
+                    }
+                    // like blueprint, the value is evaluated before the variable is modified
+                    for (name, span) in a.value.references() {
+                        check_reference(name, span, &variable_spans, &mut errors);
+                        references.entry(name.to_string()).or_insert(span);
+                    }
+                    let k = &a.name;
+                    let result = match check_assignment(&a, &variable_spans, &references) {
+                        Err(e) => Err(e),
+                        Ok(()) => match variables.get_mut(k) {
+                            Some(prev) => append_variable(k, prev, value, a.value_span()),
+                            None => {
+                                variables.insert(k.clone(), value);
+                                Ok(())
+                            }
+                        },
+                    };
+                    match result {
+                        Ok(()) => variable_spans.entry(k.clone()).or_default().push(EntrySpan {
+                            key: a.name_span(),
+                            value: a.value_span(),
+                        }),
+                        Err(e) => errors.push(e),
+                    }
+                    statements.push(Statement::Assignment(a));
+                }
+            }
+        }
+        errors.sort_by_key(|e| e.position());
+        let blueprint = BluePrint {
+            variables,
+            modules,
+            statements,
+            variable_spans: SourceInfo(variable_spans),
+        };
+        (blueprint, errors)
+    }
+    /// evaluate the blueprint file: variables are substituted in the order of
+    /// the statements, and every `+` is computed, in variables and in module
+    /// properties.
+    ///
+    /// Selects and calls are kept, as they depend on the configuration.
+    /// Undefined variables and mismatched types are reported with their location.
+    pub fn evaluate(&self) -> Result<Self, Error> {
+        let mut variables = IndexMap::new();
+        let mut modules = Vec::new();
+        for statement in &self.statements {
+            match statement {
+                Statement::Module(idx) => modules.push(self.modules[*idx].resolved(&variables)?),
+                Statement::Assignment(a) => {
+                    let value = a.value.evaluate(&variables)?;
+                    match (a.op, variables.get_mut(&a.name)) {
+                        (AssignOp::Append, Some(prev)) => {
+                            append_variable(&a.name, prev, value, a.value_span())?
+                        }
+                        (AssignOp::Append, None) => Err(Error::semantic(
+                            a.name_span(),
+                            format!("modified non-existent variable `{}` with +=", a.name),
+                        ))?,
+                        (AssignOp::Assign, _) => {
+                            variables.insert(a.name.clone(), value);
+                        }
+                    }
                 }
-                Ok(result)
             }
-            Err(err) => Err(format_err(input, err)),
         }
+        Ok(BluePrint {
+            variables,
+            modules,
+            statements: self.statements.clone(),
+            variable_spans: self.variable_spans.clone(),
+        })
     }
     /// parse an Android.bp file from a file path
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
-        let input = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
-        Self::parse(&input)
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let input = std::fs::read_to_string(path).map_err(|error| Error::Io {
+            file: path.to_path_buf(),
+            error,
+        })?;
+        Self::parse(&input).map_err(|e| e.in_file(path))
+    }
+    /// parse an Android.bp file from a file path, recovering from errors.
+    /// See [`BluePrint::parse_recovering`].
+    pub fn from_file_recovering<P: AsRef<Path>>(path: P) -> Result<(Self, Vec<Error>), Error> {
+        let path = path.as_ref();
+        let input = std::fs::read_to_string(path).map_err(|error| Error::Io {
+            file: path.to_path_buf(),
+            error,
+        })?;
+        let (blueprint, errors) = Self::parse_recovering(&input);
+        Ok((blueprint, errors.into_iter().map(|e| e.in_file(path)).collect()))
     }
     /// get all modules of a specific type
     pub fn modules_by_type<'a>(&'a self, typ: &'static str) -> impl Iterator<Item = &'a Module> {
         self.modules.iter().filter(move |b| b.typ == typ)
     }
+    /// get the locations of the definitions of a variable,
+    /// the `=` assignment first, followed by every `+=`
+    pub fn variable_spans(&self, name: &str) -> &[EntrySpan] {
+        self.variable_spans
+            .get(name)
+            .map(|v| v.as_slice())
+            .unwrap_or_default()
+    }
 }
@@ -0,0 +1,130 @@
+use crate::error::Error;
+use crate::json::{self, Json};
+use crate::parser::{Map, Module, Value};
+use crate::soong_config::{substitute, SoongConfig};
+use crate::span::{LineIndex, Span};
+use crate::variant::append_properties;
+use indexmap::IndexMap;
+use std::path::Path;
+
+/// the product variables of a product, as written by its build in
+/// `out/soong/soong.variables`, like `Platform_sdk_version` or `Debuggable`.
+/// Variables set to `null` are left out.
+#[derive(Debug, PartialEq, Clone, Eq, Default)]
+pub struct ProductVariables(pub IndexMap<String, Value>);
+
+/// the value of a JSON value, `None` for `null`
+fn json_value(json: Json) -> Option<Value> {
+    Some(match json {
+        Json::Null => return None,
+        Json::Bool(b) => Value::Boolean(b),
+        Json::Integer(i) => Value::Integer(i),
+        Json::String(s) => Value::String(s),
+        Json::Array(a) => Value::Array(a.into_iter().filter_map(json_value).collect()),
+        Json::Object(o) => Value::Map(Map::from(
+            o.into_iter()
+                .filter_map(|(k, v)| Some((k, json_value(v)?)))
+                .collect::<IndexMap<_, _>>(),
+        )),
+    })
+}
+
+impl ProductVariables {
+    /// parse the content of a `soong.variables` file
+    pub fn parse(input: &str) -> Result<Self, Error> {
+        match json_value(json::parse(input)?) {
+            Some(Value::Map(m)) => Ok(ProductVariables(m.0)),
+            _ => {
+                // the document is a single value, surrounded by spaces
+                let index = LineIndex::new(input);
+                let span = Span {
+                    start: index.position(input.len() - input.trim_start().len()),
+                    end: index.position(input.trim_end().len()),
+                };
+                Err(Error::semantic(span, "product variables must be a JSON object"))
+            }
+        }
+    }
+    /// read a `soong.variables` file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let input = std::fs::read_to_string(path).map_err(|error| Error::Io {
+            file: path.to_path_buf(),
+            error,
+        })?;
+        Self::parse(&input).map_err(|e| e.in_file(path))
+    }
+    /// get a variable by its name in `soong.variables`, like `Platform_sdk_version`
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.0.get(name)
+    }
+    /// the soong config variables of the product, from `VendorVars`
+    pub fn soong_config(&self) -> SoongConfig {
+        let mut config = SoongConfig::default();
+        let Some(Value::Map(namespaces)) = self.get("VendorVars") else {
+            return config;
+        };
+        for (namespace, variables) in namespaces.iter() {
+            let Value::Map(variables) = variables else {
+                continue;
+            };
+            for (variable, value) in variables.iter() {
+                if let Value::String(value) = value {
+                    config.set(namespace, variable, value);
+                }
+            }
+        }
+        config
+    }
+    /// the variable of a `product_variables` block or of a `product_variable()`
+    /// select: `platform_sdk_version` is `Platform_sdk_version`
+    pub(crate) fn for_block(&self, block: &str) -> Option<&Value> {
+        let mut chars = block.chars();
+        let first = chars.next()?;
+        self.get(&(first.to_uppercase().collect::<String>() + chars.as_str()))
+    }
+}
+
+impl Module {
+    /// the module with the `product_variables` blocks of the variables set by
+    /// the product appended to its properties, see [`Module::for_variant`].
+    ///
+    /// Blocks of boolean variables apply when they are true. Blocks of integer
+    /// and string variables apply when they are set, with `%d` or `%s`
+    /// replaced by the value in their strings, as soong does.
+    pub fn with_product_variables(&self, variables: &ProductVariables) -> Result<Module, Error> {
+        let mut module = self.clone();
+        module.remove("product_variables");
+        let blocks = match self.get("product_variables") {
+            None => return Ok(module),
+            Some(Value::Map(blocks)) => blocks,
+            Some(_) => {
+                let span = self.value_span("product_variables").unwrap_or(self.span());
+                return Err(Error::semantic(span, "product_variables must be a map"));
+            }
+        };
+        for (name, block) in blocks.iter() {
+            let full_name = format!("product_variables.{}", name);
+            let Value::Map(block) = block else {
+                let span = blocks.value_span(name).unwrap_or(blocks.span());
+                return Err(Error::semantic(span, format!("{} must be a map", full_name)));
+            };
+            let block = match variables.for_block(name) {
+                Some(Value::Boolean(true)) => block.clone(),
+                Some(Value::Array(a)) if !a.is_empty() => block.clone(),
+                Some(Value::Integer(i)) => substitute_map(block, "%d", &i.to_string()),
+                Some(Value::String(s)) => substitute_map(block, "%s", s),
+                _ => continue,
+            };
+            append_properties(&mut module, &block, &full_name)?;
+        }
+        Ok(module)
+    }
+}
+
+fn substitute_map(block: &Map, verb: &str, value: &str) -> Map {
+    match substitute(&Value::Map(block.clone()), verb, value) {
+        Value::Map(m) => m,
+        _ => unreachable!("substitute keeps the type of the value"),
+    }
+}
@@ -0,0 +1,154 @@
+use crate::error::Error;
+use crate::glob::{strings_with_spans, FileSystem};
+use crate::parser::{BluePrint, Module};
+use crate::span::Span;
+use std::path::Path;
+
+/// a reference to the outputs of another module in a path property:
+/// `:name`, or `:name{tag}` for some of them, like `:gen_headers{out.h}`
+#[derive(Debug, PartialEq, Clone, Eq, Hash)]
+pub struct ModuleReference {
+    pub name: String,
+    /// the tag between braces, like `.jar` or `out.h`
+    pub tag: Option<String>,
+}
+
+impl ModuleReference {
+    /// parse a reference, `None` for a plain path
+    pub fn parse(path: &str) -> Option<Self> {
+        let reference = path.strip_prefix(':')?;
+        let (name, tag) = match reference.strip_suffix('}').and_then(|r| r.split_once('{')) {
+            Some((name, tag)) => (name, Some(tag.to_string()).filter(|t| !t.is_empty())),
+            None => (reference, None),
+        };
+        Some(ModuleReference {
+            name: name.to_string(),
+            tag,
+        })
+    }
+}
+
+/// a path of a path property, with the references to other modules resolved
+#[derive(Debug, PartialEq, Clone, Eq, Hash)]
+pub enum SourcePath {
+    /// a file of the tree, relative to its root
+    File(String),
+    /// a file generated by a genrule, as declared in its `out`
+    Generated { module: String, path: String },
+    /// the outputs of another module, like the jar of a `java_library`, which
+    /// depend on how it is built
+    Module { module: String, tag: Option<String> },
+}
+
+/// `path` relative to the root, given the directory of the module
+fn join(dir: &Path, path: &str) -> String {
+    dir.join(path).to_string_lossy().into_owned()
+}
+
+impl Module {
+    fn is_genrule(&self) -> bool {
+        self.typ == "genrule" || self.typ.ends_with("_genrule")
+    }
+    /// see [`Module::source_paths_with`], `stack` holding the filegroups being resolved
+    fn collect_source_paths<'a, F: FileSystem + ?Sized>(
+        &self,
+        property: &str,
+        dir: &Path,
+        fs: &F,
+        find: &impl Fn(&str) -> Option<(&'a Module, &'a Path)>,
+        stack: &mut Vec<String>,
+    ) -> Result<Vec<SourcePath>, Error> {
+        let mut paths = Vec::new();
+        for (path, span) in self.paths_with_spans(property, dir, fs)? {
+            let resolved = match ModuleReference::parse(&path) {
+                None => vec![SourcePath::File(join(dir, &path))],
+                Some(reference) => resolve(&reference, property, span, fs, find, stack)?,
+            };
+            for path in resolved {
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+        Ok(paths)
+    }
+    /// the paths of a path property like `srcs`, `data` or `tool_files`,
+    /// relative to the root of the tree, with globs expanded, see
+    /// [`Module::paths`], and references to other modules resolved.
+    ///
+    /// `dir` is the directory of the blueprint file, relative to the root, and
+    /// `find` looks up a module by name, with the directory of its blueprint
+    /// file. References to filegroups are replaced by their `srcs`, and
+    /// references to genrules by their `out`, or by the output named by the
+    /// tag. Undefined modules, unknown tags and cycles are reported at the
+    /// reference.
+    pub fn source_paths_with<'a, F: FileSystem + ?Sized>(
+        &self,
+        property: &str,
+        dir: &Path,
+        fs: &F,
+        find: impl Fn(&str) -> Option<(&'a Module, &'a Path)>,
+    ) -> Result<Vec<SourcePath>, Error> {
+        let mut stack = vec![self.get_string("name").cloned().unwrap_or_default()];
+        self.collect_source_paths(property, dir, fs, &find, &mut stack)
+    }
+}
+
+/// the paths a reference stands for
+fn resolve<'a, F: FileSystem + ?Sized>(
+    reference: &ModuleReference,
+    property: &str,
+    span: Span,
+    fs: &F,
+    find: &impl Fn(&str) -> Option<(&'a Module, &'a Path)>,
+    stack: &mut Vec<String>,
+) -> Result<Vec<SourcePath>, Error> {
+    let name = &reference.name;
+    let (module, dir) = find(name).ok_or_else(|| {
+        Error::semantic(span, format!("\"{}\" depends on undefined module \"{}\"", property, name))
+    })?;
+    let no_tag = |tag: &str| Error::semantic(span, format!("module \"{}\" has no output tag \"{}\"", name, tag));
+    if module.typ == "filegroup" {
+        if let Some(tag) = &reference.tag {
+            return Err(no_tag(tag));
+        }
+        if stack.contains(name) {
+            let message = format!("reference cycle: {} -> {}", stack.join(" -> "), name);
+            return Err(Error::semantic(span, message));
+        }
+        stack.push(name.clone());
+        let paths = module.collect_source_paths("srcs", dir, fs, find, stack)?;
+        stack.pop();
+        return Ok(paths);
+    }
+    if module.is_genrule() {
+        let outs = strings_with_spans(module, "out")?;
+        let generated = |path: &str| SourcePath::Generated {
+            module: name.clone(),
+            path: path.to_string(),
+        };
+        return match &reference.tag {
+            None => Ok(outs.iter().map(|(out, _)| generated(out)).collect()),
+            Some(tag) if outs.iter().any(|(out, _)| out == tag) => Ok(vec![generated(tag)]),
+            Some(tag) => Err(no_tag(tag)),
+        };
+    }
+    Ok(vec![SourcePath::Module {
+        module: name.clone(),
+        tag: reference.tag.clone(),
+    }])
+}
+
+impl BluePrint {
+    /// resolve a path property of a module, looking up references in this
+    /// file, see [`Module::source_paths_with`]
+    pub fn source_paths<F: FileSystem + ?Sized>(
+        &self,
+        module: &Module,
+        property: &str,
+        dir: &Path,
+        fs: &F,
+    ) -> Result<Vec<SourcePath>, Error> {
+        module.source_paths_with(property, dir, fs, |name| Some((self.module(name)?, dir)))
+    }
+}
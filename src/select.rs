@@ -0,0 +1,225 @@
+use crate::error::Error;
+use crate::parser::{Condition, Module, Select, SelectPattern, Value};
+use crate::product::ProductVariables;
+use crate::soong_config::SoongConfig;
+use crate::span::Span;
+use crate::variant::Variant;
+use indexmap::IndexMap;
+use std::fmt;
+
+/// the configuration selects are resolved against
+#[derive(Debug, PartialEq, Clone, Eq, Default)]
+pub struct Configuration {
+    /// the os of the variant, for `os()`
+    pub os: String,
+    /// the arch of the variant, for `arch()`
+    pub arch: String,
+    /// for `soong_config_variable("namespace", "variable")`
+    pub soong_config: SoongConfig,
+    /// the release flags of the build, strings or booleans, for `release_flag("RELEASE_FOO")`
+    pub release_flags: IndexMap<String, Value>,
+    /// for `product_variable("debuggable")`
+    pub product_variables: ProductVariables,
+    /// the variations of the variant, like `image` to `vendor`, for
+    /// `variant("image")`. Other variations are unset.
+    pub variant: IndexMap<String, String>,
+}
+
+impl Configuration {
+    /// the configuration of a variant, without variables nor flags
+    pub fn new(variant: &Variant) -> Self {
+        Configuration {
+            os: variant.os.clone(),
+            arch: variant.arch.clone(),
+            variant: [("image".to_string(), variant.image.clone())].into_iter().collect(),
+            ..Default::default()
+        }
+    }
+    /// the value of a condition, `None` when it is not set
+    fn condition(&self, condition: &Condition) -> Result<Option<Value>, String> {
+        let args: Vec<&str> = condition.args.iter().map(String::as_str).collect();
+        let string = |s: &str| Some(Value::from(s)).filter(|_| !s.is_empty());
+        Ok(match (condition.name.as_str(), args.as_slice()) {
+            ("os", []) => string(&self.os),
+            ("arch", []) => string(&self.arch),
+            ("soong_config_variable", [namespace, variable]) => {
+                self.soong_config.get(namespace, variable).map(Value::from)
+            }
+            ("release_flag", [flag]) => self.release_flags.get(*flag).cloned(),
+            ("product_variable", [name]) => match self.product_variables.for_block(name) {
+                Some(Value::Integer(i)) => Some(Value::String(i.to_string())),
+                v => v.cloned(),
+            },
+            ("variant", [variation]) => self.variant.get(*variation).map(|v| Value::from(v.as_str())),
+            ("os" | "arch" | "soong_config_variable" | "release_flag" | "product_variable" | "variant", _) => {
+                return Err(format!("wrong number of arguments to {}", condition));
+            }
+            _ => return Err(format!("unknown select condition {}", condition)),
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args: Vec<String> = self.args.iter().map(|a| format!("{:?}", a)).collect();
+        write!(f, "{}({})", self.name, args.join(", "))
+    }
+}
+
+/// the pattern of a case matches the value of a condition
+fn matches(pattern: &SelectPattern, value: Option<&Value>) -> bool {
+    match (pattern, value) {
+        (SelectPattern::Default, _) => true,
+        (_, None) => false,
+        (SelectPattern::Any(_), Some(_)) => true,
+        (SelectPattern::String(p), Some(Value::String(v))) => p == v,
+        (SelectPattern::Boolean(p), Some(Value::Boolean(v))) => p == v,
+        // soong config variables are strings, even the boolean ones
+        (SelectPattern::Boolean(p), Some(Value::String(v))) => *v == p.to_string(),
+        _ => false,
+    }
+}
+
+/// `value`, `(value, value)`, as written in a select
+fn tuple<T>(items: &[T], f: impl Fn(&T) -> String) -> String {
+    match items {
+        [item] => f(item),
+        items => format!("({})", items.iter().map(f).collect::<Vec<_>>().join(", ")),
+    }
+}
+
+impl Select {
+    /// the first case matching the configuration, with the values bound by its
+    /// `any @ x` patterns
+    fn choose(&self, config: &Configuration) -> Result<(Option<&Value>, IndexMap<String, Value>), String> {
+        let values = self
+            .conditions
+            .iter()
+            .map(|c| config.condition(c))
+            .collect::<Result<Vec<_>, _>>()?;
+        for case in &self.cases {
+            let matched = match case.patterns.as_slice() {
+                [SelectPattern::Default] => true,
+                patterns if patterns.len() == values.len() => {
+                    patterns.iter().zip(&values).all(|(p, v)| matches(p, v.as_ref()))
+                }
+                patterns => {
+                    return Err(format!(
+                        "select case has {} patterns for {} conditions",
+                        patterns.len(),
+                        values.len()
+                    ));
+                }
+            };
+            if matched {
+                let bindings = case
+                    .patterns
+                    .iter()
+                    .zip(&values)
+                    .filter_map(|(p, v)| match (p, v) {
+                        (SelectPattern::Any(Some(binding)), Some(v)) => Some((binding.clone(), v.clone())),
+                        _ => None,
+                    })
+                    .collect();
+                return Ok((case.value.as_ref(), bindings));
+            }
+        }
+        Err(format!(
+            "no case of the select on {} matches {}, and it has no default",
+            tuple(&self.conditions, Condition::to_string),
+            tuple(&values, |v| match v {
+                Some(Value::String(s)) => format!("{:?}", s),
+                Some(Value::Boolean(b)) => b.to_string(),
+                _ => "unset".to_string(),
+            }),
+        ))
+    }
+}
+
+/// the value with its selects replaced by the chosen cases, `None` when it is
+/// unset. Unset operands of a `+` are skipped. Errors are reported at `span`.
+fn resolve(
+    value: &Value,
+    span: Span,
+    config: &Configuration,
+    bindings: &IndexMap<String, Value>,
+) -> Result<Option<Value>, Error> {
+    let error = |message: String| Error::semantic(span, message);
+    Ok(Some(match value {
+        Value::Select(s) => {
+            let (chosen, mut bound) = s.choose(config).map_err(error)?;
+            let Some(chosen) = chosen else {
+                return Ok(None);
+            };
+            for (k, v) in bindings {
+                bound.entry(k.clone()).or_insert_with(|| v.clone());
+            }
+            return resolve(chosen, span, config, &bound);
+        }
+        Value::Ident(name) => bindings
+            .get(name)
+            .cloned()
+            .ok_or_else(|| error(format!("variable `{}` not found", name)))?,
+        Value::ConcatExpr(values) => {
+            let mut result: Option<Value> = None;
+            for v in values {
+                if let Some(v) = resolve(v, span, config, bindings)? {
+                    result = Some(match result {
+                        Some(r) => r.plus(v).map_err(error)?,
+                        None => v,
+                    });
+                }
+            }
+            return Ok(result);
+        }
+        Value::Array(a) => Value::Array(
+            a.iter()
+                .filter_map(|v| resolve(v, span, config, bindings).transpose())
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Map(m) => {
+            let mut resolved = m.clone();
+            for (k, v) in m.iter() {
+                let span = m.value_span(k).unwrap_or(span);
+                match resolve(v, span, config, bindings)? {
+                    Some(v) => resolved[k] = v,
+                    None => {
+                        resolved.shift_remove(k);
+                    }
+                }
+            }
+            Value::Map(resolved)
+        }
+        Value::Function(f) => {
+            let mut f = f.clone();
+            f.args = f
+                .args
+                .iter()
+                .filter_map(|v| resolve(v, span, config, bindings).transpose())
+                .collect::<Result<_, _>>()?;
+            Value::Function(f)
+        }
+        v => v.clone(),
+    }))
+}
+
+impl Module {
+    /// the module with every `select` replaced by the case matching the
+    /// configuration. A property set to `unset` is removed, and the values
+    /// of `any @ x` bindings are substituted.
+    ///
+    /// Selects without a matching case nor a `default` are reported, as soong
+    /// does. The module should be evaluated first, see [`crate::BluePrint::evaluate`].
+    pub fn with_configuration(&self, config: &Configuration) -> Result<Module, Error> {
+        let mut module = self.clone();
+        for (k, v) in self.entries.iter() {
+            let span = self.value_span(k).unwrap_or(self.span());
+            match resolve(v, span, config, &IndexMap::new())? {
+                Some(resolved) if resolved == *v => {}
+                Some(resolved) => module.set(k, resolved),
+                None => module.remove(k),
+            }
+        }
+        Ok(module)
+    }
+}
@@ -0,0 +1,335 @@
+//! module types declared with `soong_config_module_type`
+//!
+//! Vendor trees declare module types whose properties depend on soong config
+//! variables, set by the product:
+//!
+//! ```
+//! use android_bp::soong_config::ModuleTypes;
+//! use android_bp::{BluePrint, SoongConfig};
+//!
+//! let bp = BluePrint::parse(r#"
+//! soong_config_module_type {
+//!     name: "acme_cc_defaults",
+//!     module_type: "cc_defaults",
+//!     config_namespace: "acme",
+//!     bool_variables: ["feature"],
+//!     properties: ["cflags"],
+//! }
+//! acme_cc_defaults {
+//!     name: "acme_defaults",
+//!     soong_config_variables: {
+//!         feature: { cflags: ["-DFEATURE"] },
+//!     },
+//! }
+//! "#).unwrap();
+//! let types = ModuleTypes::declared_in(&bp).unwrap();
+//! let mut config = SoongConfig::default();
+//! config.set("acme", "feature", "true");
+//! let bp = types.apply(&bp, &config).unwrap();
+//! assert_eq!(bp.modules[1].typ, "cc_defaults");
+//! assert_eq!(bp.modules[1].get_array("cflags").unwrap(), &vec!["-DFEATURE".into()]);
+//! ```
+
+use crate::error::Error;
+use crate::parser::{BluePrint, Map, Module, Value};
+use crate::variant::append_properties;
+use indexmap::IndexMap;
+
+/// values of the soong config variables of a product, by namespace and by
+/// variable, as set by `SOONG_CONFIG_<namespace>_<variable>` in its makefiles
+#[derive(Debug, PartialEq, Clone, Eq, Default)]
+pub struct SoongConfig(pub IndexMap<String, IndexMap<String, String>>);
+
+impl SoongConfig {
+    /// set the value of a variable
+    pub fn set(&mut self, namespace: &str, variable: &str, value: &str) -> &mut Self {
+        self.0
+            .entry(namespace.to_string())
+            .or_default()
+            .insert(variable.to_string(), value.to_string());
+        self
+    }
+    /// get the value of a variable
+    pub fn get(&self, namespace: &str, variable: &str) -> Option<&str> {
+        self.0.get(namespace)?.get(variable).map(String::as_str)
+    }
+}
+
+/// a variable a soong config module type depends on
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub enum Variable {
+    /// declared by `soong_config_string_variable`, with its possible values
+    String { name: String, values: Vec<String> },
+    /// declared by `soong_config_bool_variable` or in `bool_variables`
+    Bool(String),
+    /// declared in `value_variables`, its value replaces `%s` in the properties
+    Value(String),
+}
+
+impl Variable {
+    pub fn name(&self) -> &str {
+        match self {
+            Variable::String { name, .. } | Variable::Bool(name) | Variable::Value(name) => name,
+        }
+    }
+}
+
+/// a module type declared by `soong_config_module_type`
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct ModuleType {
+    pub name: String,
+    /// the type of the modules it creates, like `cc_defaults`
+    pub module_type: String,
+    pub config_namespace: String,
+    /// string variables first, then bool and value variables, as soong applies them
+    pub variables: Vec<Variable>,
+    /// the properties the variables can set, like `cflags` or `target.android.cflags`
+    pub properties: Vec<String>,
+}
+
+/// the list of strings of a property, empty when not set
+fn strings(module: &Module, key: &str) -> Result<Vec<String>, Error> {
+    match module.get(key) {
+        None => Ok(Vec::new()),
+        Some(Value::Array(a)) => a
+            .iter()
+            .map(|v| match v {
+                Value::String(s) => Ok(s.clone()),
+                _ => Err(()),
+            })
+            .collect::<Result<_, _>>()
+            .map_err(|_| property_error(module, key, "must be a list of strings")),
+        Some(_) => Err(property_error(module, key, "must be a list of strings")),
+    }
+}
+
+/// a string property, which must be set
+fn string(module: &Module, key: &str) -> Result<String, Error> {
+    module
+        .get_string(key)
+        .cloned()
+        .ok_or_else(|| property_error(module, key, "must be set to a string"))
+}
+
+fn property_error(module: &Module, key: &str, message: &str) -> Error {
+    let span = module.value_span(key).unwrap_or(module.span());
+    Error::semantic(span, format!("{}: \"{}\" {}", module.typ, key, message))
+}
+
+/// replace the format `verb`, like `%s`, with `value` in the strings of a value
+pub(crate) fn substitute(v: &Value, verb: &str, value: &str) -> Value {
+    match v {
+        Value::String(s) => Value::String(s.replace(verb, value)),
+        Value::Array(a) => Value::Array(a.iter().map(|v| substitute(v, verb, value)).collect()),
+        Value::Map(m) => {
+            let mut m = m.clone();
+            m.values_mut().for_each(|v| *v = substitute(v, verb, value));
+            Value::Map(m)
+        }
+        v => v.clone(),
+    }
+}
+
+/// the block of a `soong_config_variables` entry, which must be a map
+fn block<'a>(blocks: &'a Map, key: &str, what: &str) -> Result<&'a Map, Error> {
+    match &blocks[key] {
+        Value::Map(m) => Ok(m),
+        _ => {
+            let span = blocks.value_span(key).unwrap_or(blocks.span());
+            Err(Error::semantic(span, format!("{} must be a map", what)))
+        }
+    }
+}
+
+impl ModuleType {
+    /// check that a conditional block only sets declared properties
+    fn check_properties(&self, block: &Map, what: &str) -> Result<(), Error> {
+        for k in block.keys() {
+            let declared = self
+                .properties
+                .iter()
+                .any(|p| p == k || p.strip_prefix(k.as_str()).is_some_and(|rest| rest.starts_with('.')));
+            if !declared {
+                let span = block.key_span(k).unwrap_or(block.span());
+                return Err(Error::semantic(
+                    span,
+                    format!("{}: property \"{}\" is not declared in module type \"{}\"", what, k, self.name),
+                ));
+            }
+        }
+        Ok(())
+    }
+    /// the module with the blocks of `soong_config_variables` matching the
+    /// config appended to its properties, turned into a module of the
+    /// underlying type.
+    ///
+    /// Blocks for undeclared variables, values or properties are reported.
+    pub fn apply(&self, module: &Module, config: &SoongConfig) -> Result<Module, Error> {
+        let mut result = module.clone();
+        result.typ = self.module_type.clone();
+        result.remove("soong_config_variables");
+        let blocks = match module.get("soong_config_variables") {
+            None => return Ok(result),
+            Some(Value::Map(blocks)) => blocks,
+            Some(_) => {
+                return Err(property_error(module, "soong_config_variables", "must be a map"));
+            }
+        };
+        if let Some(k) = blocks.keys().find(|k| !self.variables.iter().any(|v| v.name() == *k)) {
+            let span = blocks.key_span(k).unwrap_or(blocks.span());
+            return Err(Error::semantic(
+                span,
+                format!("unknown variable \"{}\" of module type \"{}\"", k, self.name),
+            ));
+        }
+        for variable in &self.variables {
+            let name = variable.name();
+            if !blocks.contains_key(name) {
+                continue;
+            }
+            let what = format!("soong_config_variables.{}", name);
+            let conditions = block(blocks, name, &what)?;
+            let value = config.get(&self.config_namespace, name);
+            let default = || -> Result<Option<Map>, Error> {
+                match conditions.get("conditions_default") {
+                    Some(_) => Ok(Some(block(conditions, "conditions_default", &what)?.clone())),
+                    None => Ok(None),
+                }
+            };
+            let without_default = || {
+                let mut m = conditions.clone();
+                m.shift_remove("conditions_default");
+                m
+            };
+            let chosen = match variable {
+                Variable::String { values, .. } => {
+                    for k in conditions.keys().filter(|k| *k != "conditions_default") {
+                        if !values.contains(k) {
+                            let span = conditions.key_span(k).unwrap_or(conditions.span());
+                            return Err(Error::semantic(
+                                span,
+                                format!("{}: unknown value \"{}\"", what, k),
+                            ));
+                        }
+                        block(conditions, k, &format!("{}.{}", what, k))?;
+                    }
+                    match value.filter(|v| conditions.contains_key(*v)) {
+                        Some(v) => Some(block(conditions, v, &what)?.clone()),
+                        None => default()?,
+                    }
+                }
+                Variable::Bool(_) => match value {
+                    Some("true") => Some(without_default()),
+                    _ => default()?,
+                },
+                Variable::Value(_) => match value {
+                    Some(v) => match substitute(&Value::Map(without_default()), "%s", v) {
+                        Value::Map(m) => Some(m),
+                        _ => unreachable!("substitute keeps the type of the value"),
+                    },
+                    None => default()?,
+                },
+            };
+            if let Some(chosen) = chosen {
+                self.check_properties(&chosen, &what)?;
+                append_properties(&mut result, &chosen, &what)?;
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// the soong config module types usable in a blueprint file
+#[derive(Debug, PartialEq, Clone, Eq, Default)]
+pub struct ModuleTypes(pub IndexMap<String, ModuleType>);
+
+impl ModuleTypes {
+    /// the module types declared in a file, with the variables declared along
+    pub fn declared_in(bp: &BluePrint) -> Result<Self, Error> {
+        let mut string_variables = IndexMap::new();
+        let mut bool_variables = Vec::new();
+        for m in &bp.modules {
+            match m.typ.as_str() {
+                "soong_config_string_variable" => {
+                    string_variables.insert(string(m, "name")?, strings(m, "values")?);
+                }
+                "soong_config_bool_variable" => bool_variables.push(string(m, "name")?),
+                _ => {}
+            }
+        }
+        let mut types = IndexMap::new();
+        for m in bp.modules_by_type("soong_config_module_type") {
+            let mut variables = Vec::new();
+            for name in strings(m, "variables")? {
+                variables.push(match string_variables.get(&name) {
+                    Some(values) => Variable::String {
+                        name,
+                        values: values.clone(),
+                    },
+                    None if bool_variables.contains(&name) => Variable::Bool(name),
+                    None => {
+                        let message = format!("undeclared variable \"{}\"", name);
+                        return Err(property_error(m, "variables", &message));
+                    }
+                });
+            }
+            variables.extend(strings(m, "bool_variables")?.into_iter().map(Variable::Bool));
+            variables.extend(strings(m, "value_variables")?.into_iter().map(Variable::Value));
+            let module_type = ModuleType {
+                name: string(m, "name")?,
+                module_type: string(m, "module_type")?,
+                config_namespace: string(m, "config_namespace")?,
+                variables,
+                properties: strings(m, "properties")?,
+            };
+            types.insert(module_type.name.clone(), module_type);
+        }
+        Ok(ModuleTypes(types))
+    }
+    /// the module types usable in a file: the ones it declares, and the ones it
+    /// imports with `soong_config_module_type_import`.
+    ///
+    /// `load` reads the file of an import, given its `from` path, relative to
+    /// the root of the tree, like
+    /// `|path| BluePrint::from_file(root.join(path))`.
+    pub fn for_file(
+        bp: &BluePrint,
+        mut load: impl FnMut(&str) -> Result<BluePrint, Error>,
+    ) -> Result<Self, Error> {
+        let mut types = Self::declared_in(bp)?;
+        for import in bp.modules_by_type("soong_config_module_type_import") {
+            let from = string(import, "from")?;
+            let declared = Self::declared_in(&load(&from)?)?;
+            for name in strings(import, "module_types")? {
+                let module_type = declared.0.get(&name).ok_or_else(|| {
+                    let message = format!("module type \"{}\" is not declared in \"{}\"", name, from);
+                    property_error(import, "module_types", &message)
+                })?;
+                types.0.insert(name, module_type.clone());
+            }
+        }
+        Ok(types)
+    }
+    pub fn get(&self, name: &str) -> Option<&ModuleType> {
+        self.0.get(name)
+    }
+    /// the blueprint with every module of a soong config module type applied
+    /// to the config, see [`ModuleType::apply`]. The blueprint should be
+    /// evaluated first.
+    pub fn apply(&self, bp: &BluePrint, config: &SoongConfig) -> Result<BluePrint, Error> {
+        let modules = bp
+            .modules
+            .iter()
+            .map(|m| match self.get(&m.typ) {
+                Some(module_type) => module_type.apply(m, config),
+                None => Ok(m.clone()),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(BluePrint {
+            variables: bp.variables.clone(),
+            modules,
+            statements: bp.statements.clone(),
+            variable_spans: bp.variable_spans.clone(),
+        })
+    }
+}
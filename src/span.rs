@@ -0,0 +1,146 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+/// a location in a blueprint source
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Default, Hash, PartialOrd, Ord)]
+pub struct Position {
+    /// byte offset from the start of the input
+    pub offset: usize,
+    /// line number, starting at 1
+    pub line: usize,
+    /// column number in characters, starting at 1
+    pub column: usize,
+}
+
+/// a range in a blueprint source, `end` being exclusive
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Default, Hash)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+/// location of a `key: value` entry, or of a `name = value` definition
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Default, Hash)]
+pub struct EntrySpan {
+    pub key: Span,
+    pub value: Span,
+}
+
+impl Span {
+    /// the span is a location in the input. Values which were not parsed,
+    /// like the ones built with [`crate::Module::new`], have no location.
+    pub fn is_known(&self) -> bool {
+        self.start.line != 0
+    }
+    /// span of the input consumed by a parser, given its input and what it left.
+    ///
+    /// while parsing, offsets are counted from the end of the input, as only
+    /// suffixes of the input are known. They are turned into real positions
+    /// by [`LineIndex::resolve`] once the whole input has been parsed.
+    pub(crate) fn consumed(input: &str, rest: &str) -> Self {
+        Span {
+            start: Position {
+                offset: input.len(),
+                ..Default::default()
+            },
+            end: Position {
+                offset: rest.len(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// source information attached to a parsed item: locations, or the
+/// expressions its values were computed from
+///
+/// this is metadata and never takes part in equality, so that a value
+/// compares equal to the same value parsed from another place. It is not
+/// printed either, to keep the debug output of modules readable.
+#[derive(Clone, Default)]
+pub(crate) struct SourceInfo<T>(pub T);
+impl<T> fmt::Debug for SourceInfo<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("..")
+    }
+}
+impl<T> PartialEq for SourceInfo<T> {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+impl<T> Eq for SourceInfo<T> {}
+impl<T> Deref for SourceInfo<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl<T> DerefMut for SourceInfo<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// converts byte offsets to line and column numbers
+pub(crate) struct LineIndex<'a> {
+    input: &'a str,
+    line_starts: Vec<usize>,
+}
+impl<'a> LineIndex<'a> {
+    pub(crate) fn new(input: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(input.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        LineIndex { input, line_starts }
+    }
+    /// position of a byte offset in the input. A leading byte order mark
+    /// takes no column.
+    pub(crate) fn position(&self, offset: usize) -> Position {
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        let line_start = self.line_starts[line - 1];
+        let text = &self.input[line_start..offset];
+        let text = if line == 1 { text.trim_start_matches('\u{feff}') } else { text };
+        Position {
+            offset,
+            line,
+            column: text.chars().count() + 1,
+        }
+    }
+    /// turn a span built by [`Span::consumed`] into a real span
+    pub(crate) fn resolve(&self, span: &mut Span) {
+        span.start = self.position(self.input.len() - span.start.offset);
+        span.end = self.position(self.input.len() - span.end.offset);
+    }
+    pub(crate) fn resolve_entry(&self, entry: &mut EntrySpan) {
+        self.resolve(&mut entry.key);
+        self.resolve(&mut entry.value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_index() {
+        let input = "a\nbé\n\nc";
+        let index = LineIndex::new(input);
+        assert_eq!(
+            index.position(0),
+            Position { offset: 0, line: 1, column: 1 }
+        );
+        assert_eq!(
+            index.position(5),
+            Position { offset: 5, line: 2, column: 3 }
+        );
+        assert_eq!(
+            index.position(6),
+            Position { offset: 6, line: 3, column: 1 }
+        );
+        assert_eq!(
+            index.position(7),
+            Position { offset: 7, line: 4, column: 1 }
+        );
+    }
+}
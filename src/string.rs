@@ -1,147 +1,136 @@
 //! modified from nom example https://github.com/rust-bakery/nom/blob/7.1.3/examples/string.rs
 //! License MIT:
 //! from @0x7FFFFFFFFFFFFFFF and @Geal
-//! This example shows an example of how to parse an escaped string. The
-//! rules for the string are similar to JSON and rust. A string is:
+//! This parses an escaped string, with the rules of go's `strconv.Unquote`,
+//! which is what blueprint uses. A string is:
 //!
 //! - Enclosed by double quotes
-//! - Can contain any raw unescaped code point besides \ and "
-//! - Matches the following escape sequences: \b, \f, \n, \r, \t, \", \\, \/
-//! - Matches code points like Rust: \u{XXXX}, where XXXX can be up to 6
-//!   hex characters
-//! - an escape followed by whitespace consumes all whitespace between the
-//!   escape and the next non-whitespace character
+//! - Can contain any raw unescaped code point besides \, " and newline
+//! - Matches the following escape sequences: \a, \b, \f, \n, \r, \t, \v, \", \\
+//! - Matches bytes: \xXX, where XX is 2 hexadecimal numerals, and \OOO, where
+//!   OOO is 3 octal numerals
+//! - Matches code points: \uXXXX and \UXXXXXXXX, where XXXX and XXXXXXXX are
+//!   4 and 8 hexadecimal numerals
+//!
+//! Any other escape is an error, as it is for go.
+//! Escaped bytes are assembled as in go, so "\xc3\xa9" is "é". The resulting
+//! string must be valid UTF-8.
+//!
+//! Raw strings are enclosed by backticks, can span several lines and have no
+//! escapes. As in go, carriage returns are dropped from their value.
 
 use nom::branch::alt;
-use nom::bytes::streaming::{is_not, take_while_m_n};
-use nom::character::streaming::{char, multispace1};
-use nom::combinator::{map, map_opt, map_res, value, verify};
-use nom::error::{FromExternalError, ParseError};
+use nom::bytes::complete::{is_not, take_till, take_while_m_n};
+use nom::character::complete::char;
+use nom::combinator::{cut, map, map_opt, map_res, value, verify};
+use nom::error::{context, ContextError, FromExternalError, ParseError};
 use nom::multi::fold_many0;
-use nom::sequence::{delimited, preceded, tuple};
+use nom::sequence::{delimited, preceded};
 use nom::IResult;
 
 // parser combinators are constructed from the bottom up:
 // first we write parsers for the smallest elements (escaped characters),
 // then combine them into larger parsers.
 
-/// Parse a unicode sequence, of the form u{XXXX}, where XXXX is 1 to 6
-/// hexadecimal numerals. We will combine this later with parse_escaped_char
-/// to parse sequences like \u{00AC}.
+/// Parse a unicode sequence, of the form uXXXX or UXXXXXXXX.
+/// We will combine this later with parse_escaped_char
+/// to parse sequences like \u00AC.
 fn parse_unicode<'a, E>(input: &'a str) -> IResult<&'a str, char, E>
 where
     E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
 {
     // `take_while_m_n` parses between `m` and `n` bytes (inclusive) that match
-    // a predicate. `parse_hex` here parses between 1 and 6 hexadecimal numerals.
-    let parse_hex = take_while_m_n(1, 6, |c: char| c.is_ascii_hexdigit());
+    // a predicate. Here, exactly 4 or 8 hexadecimal numerals.
+    let parse_hex = |n| take_while_m_n(n, n, |c: char| c.is_ascii_hexdigit());
 
     // `preceded` takes a prefix parser, and if it succeeds, returns the result
-    // of the body parser. In this case, it parses u{XXXX}.
-    let parse_delimited_hex = preceded(
-        char('u'),
-        // `delimited` is like `preceded`, but it parses both a prefix and a suffix.
-        // It returns the result of the middle parser. In this case, it parses
-        // {XXXX}, where XXXX is 1 to 6 hex numerals, and returns XXXX
-        delimited(char('{'), parse_hex, char('}')),
-    );
+    // of the body parser. In this case, it parses uXXXX or UXXXXXXXX.
+    let parse_prefixed_hex = alt((
+        preceded(char('u'), parse_hex(4)),
+        preceded(char('U'), parse_hex(8)),
+    ));
 
     // `map_res` takes the result of a parser and applies a function that returns
     // a Result. In this case we take the hex bytes from parse_hex and attempt to
     // convert them to a u32.
-    let parse_u32 = map_res(parse_delimited_hex, move |hex| u32::from_str_radix(hex, 16));
+    let parse_u32 = map_res(parse_prefixed_hex, move |hex| u32::from_str_radix(hex, 16));
 
     // map_opt is like map_res, but it takes an Option instead of a Result. If
     // the function returns None, map_opt returns an error. In this case, because
-    // not all u32 values are valid unicode code points, we have to fallibly
-    // convert to char with from_u32.
-    map_opt(parse_u32, |value| std::char::from_u32(value))(input)
+    // not all u32 values are valid unicode code points (surrogates, or values
+    // above 0x10FFFF, that go rejects too), we have to fallibly convert to char
+    // with from_u32.
+    map_opt(parse_u32, std::char::from_u32)(input)
 }
 
 /// Parse a hex sequence, of the form xXX, where XX is 2 hexadecimal numerals.
-/// We will combine this later with parse_escaped_char
+/// We will combine this later with parse_escaped_byte
 /// to parse sequences like \x1b.
-fn parse_hex<'a, E>(input: &'a str) -> IResult<&'a str, char, E>
+fn parse_hex<'a, E>(input: &'a str) -> IResult<&'a str, u8, E>
 where
     E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
 {
     map_res(
-        tuple((
+        preceded(
             char('x'),
-            // `take_while_m_n` parses between `m` and `n` bytes (inclusive) that match
-            // a predicate. `parse_hex` here parses 2 hexadecimal numerals.
             take_while_m_n(2, 2, |c: char| c.is_ascii_hexdigit()),
-        )),
-        |(_, hex)| {
-            let value = u8::from_str_radix(hex, 16)?;
-            Ok(value as char)
-        },
+        ),
+        |hex| u8::from_str_radix(hex, 16),
     )(input)
 }
 
-/// Parse a oct sequence, of the form 0XX, where XX is 2 octal numerals.
-/// We will combine this later with parse_escaped_char
+/// Parse an octal sequence, of the form OOO, where OOO is 3 octal numerals,
+/// up to 377. We will combine this later with parse_escaped_byte
 /// to parse sequences like \033.
-fn parse_oct<'a, E>(input: &'a str) -> IResult<&'a str, char, E>
+fn parse_oct<'a, E>(input: &'a str) -> IResult<&'a str, u8, E>
 where
     E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
 {
     map_res(
-        tuple((
-            char('0'),
-            // `take_while_m_n` parses between `m` and `n` bytes (inclusive) that match
-            // a predicate. `parse_hex` here parses 2 hexadecimal numerals.
-            take_while_m_n(2, 2, |c: char| c.is_ascii_hexdigit()),
-        )),
-        |(_, hex)| {
-            let value = u8::from_str_radix(hex, 8)?;
-            Ok(value as char)
-        },
+        take_while_m_n(3, 3, |c: char| c.is_digit(8)),
+        // values above 0o377 do not fit in a u8, which go rejects as well
+        |oct| u8::from_str_radix(oct, 8),
     )(input)
 }
 
-/// Parse an escaped character: \n, \t, \r, \u{00AC}, etc.
+/// Parse an escaped character: \n, \t, \r, \u00AC, etc.
 fn parse_escaped_char<'a, E>(input: &'a str) -> IResult<&'a str, char, E>
 where
     E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
 {
-    preceded(
-        char('\\'),
-        // `alt` tries each parser in sequence, returning the result of
-        // the first successful match
-        alt((
-            parse_unicode,
-            parse_hex,
-            parse_oct,
-            // The `value` parser returns a fixed value (the first argument) if its
-            // parser (the second argument) succeeds. In these cases, it looks for
-            // the marker characters (n, r, t, etc) and returns the matching
-            // character (\n, \r, \t, etc).
-            value('\n', char('n')),
-            value('\r', char('r')),
-            value('\t', char('t')),
-            value('\u{08}', char('b')),
-            value('\u{0C}', char('f')),
-            value('\\', char('\\')),
-            value('/', char('/')),
-            value('"', char('"')),
-        )),
-    )(input)
+    // `alt` tries each parser in sequence, returning the result of
+    // the first successful match
+    alt((
+        parse_unicode,
+        // The `value` parser returns a fixed value (the first argument) if its
+        // parser (the second argument) succeeds. In these cases, it looks for
+        // the marker characters (n, r, t, etc) and returns the matching
+        // character (\n, \r, \t, etc).
+        value('\u{07}', char('a')),
+        value('\u{08}', char('b')),
+        value('\u{0C}', char('f')),
+        value('\n', char('n')),
+        value('\r', char('r')),
+        value('\t', char('t')),
+        value('\u{0B}', char('v')),
+        value('\\', char('\\')),
+        value('"', char('"')),
+    ))(input)
 }
 
-/// Parse a backslash, followed by any amount of whitespace. This is used later
-/// to discard any escaped whitespace.
-fn parse_escaped_whitespace<'a, E: ParseError<&'a str>>(
-    input: &'a str,
-) -> IResult<&'a str, &'a str, E> {
-    preceded(char('\\'), multispace1)(input)
+/// Parse an escaped byte: \x1b or \033
+fn parse_escaped_byte<'a, E>(input: &'a str) -> IResult<&'a str, u8, E>
+where
+    E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
+{
+    alt((parse_hex, parse_oct))(input)
 }
 
-/// Parse a non-empty block of text that doesn't include \ or "
+/// Parse a non-empty block of text that doesn't include \, " or a newline
 fn parse_literal<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, &'a str, E> {
     // `is_not` parses a string of 0 or more characters that aren't one of the
     // given characters.
-    let not_quote_slash = is_not("\"\\");
+    let not_quote_slash = is_not("\"\\\n");
 
     // `verify` runs a parser, then runs a verification function on the output of
     // the parser. The verification function accepts out output only if it
@@ -152,51 +141,69 @@ fn parse_literal<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str,
 
 /// A string fragment contains a fragment of a string being parsed: either
 /// a non-empty Literal (a series of non-escaped characters), a single
-/// parsed escaped character, or a block of escaped whitespace.
+/// parsed escaped character, or a single escaped byte.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum StringFragment<'a> {
     Literal(&'a str),
     EscapedChar(char),
-    EscapedWS,
+    EscapedByte(u8),
 }
 
-/// Combine parse_literal, parse_escaped_whitespace, and parse_escaped_char
+/// Combine parse_literal, parse_escaped_char and parse_escaped_byte
 /// into a StringFragment.
 fn parse_fragment<'a, E>(input: &'a str) -> IResult<&'a str, StringFragment<'a>, E>
 where
-    E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
+    E: ParseError<&'a str>
+        + ContextError<&'a str>
+        + FromExternalError<&'a str, std::num::ParseIntError>,
 {
     alt((
         // The `map` combinator runs a parser, then applies a function to the output
         // of that parser.
         map(parse_literal, StringFragment::Literal),
-        map(parse_escaped_char, StringFragment::EscapedChar),
-        value(StringFragment::EscapedWS, parse_escaped_whitespace),
+        // a backslash is always an escape, so anything that does not follow
+        // is an error: `cut` prevents trying other parsers
+        preceded(
+            char('\\'),
+            cut(context(
+                "escape sequence",
+                alt((
+                    map(parse_escaped_char, StringFragment::EscapedChar),
+                    map(parse_escaped_byte, StringFragment::EscapedByte),
+                )),
+            )),
+        ),
     ))(input)
 }
 
 /// Parse a string. Use a loop of parse_fragment and push all of the fragments
-/// into an output string.
+/// into an output buffer.
 pub(crate) fn parse_string<'a, E>(input: &'a str) -> IResult<&'a str, String, E>
 where
-    E: ParseError<&'a str> + FromExternalError<&'a str, std::num::ParseIntError>,
+    E: ParseError<&'a str>
+        + ContextError<&'a str>
+        + FromExternalError<&'a str, std::num::ParseIntError>
+        + FromExternalError<&'a str, std::string::FromUtf8Error>,
 {
     // fold_many0 is the equivalent of iterator::fold. It runs a parser in a loop,
     // and for each output value, calls a folding function on each output value.
     let build_string = fold_many0(
         // Our parser function– parses a single string fragment
         parse_fragment,
-        // Our init value, an empty string
-        String::new,
+        // Our init value, an empty buffer. Escaped bytes may not be valid
+        // UTF-8 on their own, so the string is assembled as bytes.
+        Vec::new,
         // Our folding function. For each fragment, append the fragment to the
-        // string.
-        |mut string, fragment| {
+        // buffer.
+        |mut bytes: Vec<u8>, fragment| {
             match fragment {
-                StringFragment::Literal(s) => string.push_str(s),
-                StringFragment::EscapedChar(c) => string.push(c),
-                StringFragment::EscapedWS => {}
+                StringFragment::Literal(s) => bytes.extend_from_slice(s.as_bytes()),
+                StringFragment::EscapedChar(c) => {
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes())
+                }
+                StringFragment::EscapedByte(b) => bytes.push(b),
             }
-            string
+            bytes
         },
     );
 
@@ -204,11 +211,24 @@ where
     // " character, the closing delimiter " would never match. When using
     // `delimited` with a looping parser (like fold_many0), be sure that the
     // loop won't accidentally match your closing delimiter!
-    delimited(char('"'), build_string, char('"'))(input)
+    map_res(
+        delimited(char('"'), build_string, char('"')),
+        String::from_utf8,
+    )(input)
+}
+
+/// Parse a raw string: `...`
+pub(crate) fn parse_raw_string<'a, E: ParseError<&'a str>>(
+    input: &'a str,
+) -> IResult<&'a str, String, E> {
+    map(
+        delimited(char('`'), take_till(|c| c == '`'), char('`')),
+        |s: &str| s.replace('\r', ""),
+    )(input)
 }
 #[cfg(test)]
 mod tests {
-    use super::parse_string;
+    use super::{parse_raw_string, parse_string};
     use nom::error::VerboseError;
 
     #[test]
@@ -235,4 +255,46 @@ mod tests {
         let expected_output = Ok(("", "echo \"\x1b".to_string()));
         assert_eq!(parse_string::<VerboseError<&str>>(input), expected_output);
     }
+    #[test]
+    fn test_parse_go_escapes() {
+        let cases = [
+            (r#""\a\b\f\n\r\t\v\\""#, "\x07\x08\x0c\n\r\t\x0b\\"),
+            (r#""é\U0001F600""#, "é\u{1F600}"),
+            (r#""\xc3\xa9\303\251""#, "éé"),
+            (r#""\177""#, "\x7f"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                parse_string::<VerboseError<&str>>(input),
+                Ok(("", expected.to_string()))
+            );
+        }
+    }
+    #[test]
+    fn test_parse_invalid_escapes() {
+        let cases = [
+            r#""\u{00e9}""#,
+            r#""\'""#,
+            r#""\/""#,
+            r#""\q""#,
+            r#""\08""#,
+            r#""\400""#,
+            r#""\x4""#,
+            r#""\ud800""#,
+            r#""\U00110000""#,
+            r#""\xff""#,
+            "\"a\\\n  b\"",
+            "\"a\nb\"",
+        ];
+        for input in cases {
+            assert!(parse_string::<VerboseError<&str>>(input).is_err(), "{}", input);
+        }
+    }
+    #[test]
+    fn test_parse_raw_string() {
+        let input = "`echo \"$(in)\" \\\r\n  > $(out)`";
+        let expected_output = Ok(("", "echo \"$(in)\" \\\n  > $(out)".to_string()));
+        assert_eq!(parse_raw_string::<VerboseError<&str>>(input), expected_output);
+        assert!(parse_raw_string::<VerboseError<&str>>("`abc").is_err());
+    }
 }
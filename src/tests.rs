@@ -1,33 +1,44 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
 
     use std::io::Read;
 
+    use crate::expr::Expr;
     use crate::parser::*;
     use nom::error::VerboseError;
     use nom::Err;
 
+    /// the values of the elements of a parsed array
+    fn array_values((rest, exprs): (&str, Vec<Expr>)) -> (&str, Vec<Value>) {
+        (rest, exprs.iter().map(Expr::to_value).collect())
+    }
+    /// a parsed module entry, with its value and without its location
+    fn entry_value((rest, (key, expr, _)): (&str, (String, Expr, crate::EntrySpan))) -> (&str, (String, Value)) {
+        (rest, (key, expr.to_value()))
+    }
+
     #[test]
     fn test_parse_array() {
         // Test case 1: Valid input
         let input = r#"[ "value1", "value2", "value3" ]"#;
         let expected_output = Ok(("", vec!["value1".into(), "value2".into(), "value3".into()]));
-        assert_eq!(parse_array(input), expected_output);
+        assert_eq!(parse_array(input).map(array_values), expected_output);
 
         // Test case 2: Empty array
         let input = r#"[]"#;
         let expected_output = Ok(("", vec![]));
-        assert_eq!(parse_array(input), expected_output);
+        assert_eq!(parse_array(input).map(array_values), expected_output);
 
         // Test case 3: Array with whitespace
         let input = r#"[ "value1" , "value2" , "value3" ]"#;
         let expected_output = Ok(("", vec!["value1".into(), "value2".into(), "value3".into()]));
-        assert_eq!(parse_array(input), expected_output);
+        assert_eq!(parse_array(input).map(array_values), expected_output);
 
         // Test case 4: Array with empty values
         let input = r#"[ "", "", "" ]"#;
         let expected_output = Ok(("", vec!["".into(), "".into(), "".into()]));
-        assert_eq!(parse_array(input), expected_output);
+        assert_eq!(parse_array(input).map(array_values), expected_output);
 
         // Test case 5: Invalid input - missing closing bracket
         let input = r#"[ "value1", "value2", "value3""#;
@@ -40,19 +51,19 @@ mod tests {
         // Test case 6: Array with trailing comma is not an error
         let input = r#"[ "value1", "value2", "value3", ]"#;
         let expected_output = Ok(("", vec!["value1".into(), "value2".into(), "value3".into()]));
-        assert_eq!(parse_array(input), expected_output);
+        assert_eq!(parse_array(input).map(array_values), expected_output);
     }
     #[test]
     fn test_parse_entry() {
         // Test case 1: Valid input
         let input = r#"key: "value""#;
         let expected_output = Ok(("", ("key".to_string(), Value::String("value".to_string()))));
-        assert_eq!(parse_module_entry(input), expected_output);
+        assert_eq!(parse_module_entry(input, ':').map(entry_value), expected_output);
 
         // Test case 2: Valid input with whitespace
         let input = r#"  key  :   "value"  "#;
         let expected_output = Ok(("", ("key".to_string(), Value::String("value".to_string()))));
-        assert_eq!(parse_module_entry(input), expected_output);
+        assert_eq!(parse_module_entry(input, ':').map(entry_value), expected_output);
 
         // Test case 3: Valid input with array value
         let input = r#"key: [ "value1", "value2", "value3" ]"#;
@@ -63,23 +74,23 @@ mod tests {
                 Value::Array(vec!["value1".into(), "value2".into(), "value3".into()]),
             ),
         ));
-        assert_eq!(parse_module_entry(input), expected_output);
+        assert_eq!(parse_module_entry(input, ':').map(entry_value), expected_output);
 
         // Test case 4: Invalid input - missing colon
         let input = r#"key "value""#;
-        assert!(parse_module_entry(input).is_err());
+        assert!(parse_module_entry(input, ':').is_err());
 
         // Test case 5: Invalid input - missing value
         let input = r#"key:"#;
-        assert!(parse_module_entry(input).is_err());
+        assert!(parse_module_entry(input, ':').is_err());
 
         // Test case 6: Invalid input - missing key
         let input = r#":"value""#;
-        assert!(parse_module_entry(input).is_err());
+        assert!(parse_module_entry(input, ':').is_err());
 
         // Test case 7: Invalid input - missing key and value
         let input = r#":"#;
-        assert!(parse_module_entry(input).is_err());
+        assert!(parse_module_entry(input, ':').is_err());
     }
     #[test]
     fn test_parse_module() {
@@ -91,9 +102,9 @@ mod tests {
             }
         "#;
 
-        let expected_output = Module {
-            typ: "module_name".to_string(),
-            entries: vec![
+        let expected_output = Module::new(
+            "module_name",
+            vec![
                 ("key1".to_string(), Value::String("value1".to_string())),
                 ("key2".to_string(), Value::Boolean(true)),
                 (
@@ -103,7 +114,7 @@ mod tests {
             ]
             .into_iter()
             .collect(),
-        };
+        );
 
         assert_eq!(parse_module(input), Ok(("", expected_output)));
     }
@@ -136,6 +147,685 @@ mod tests {
         );
     }
     #[test]
+    fn test_source_order() {
+        let input = r#"
+zeta = 1
+alpha = 2
+mid = 3
+foo {
+    name: "foo",
+    srcs: [],
+    arch: { x86: {}, arm: {}, riscv64: {} },
+    cflags: [],
+}
+"#;
+        let bp = BluePrint::parse(input).unwrap();
+        assert_eq!(bp.variables.keys().collect::<Vec<_>>(), ["zeta", "alpha", "mid"]);
+        let module = &bp.modules[0];
+        assert_eq!(module.entries.keys().collect::<Vec<_>>(), ["name", "srcs", "arch", "cflags"]);
+        let arch = module.get_map("arch").unwrap();
+        assert_eq!(arch.keys().collect::<Vec<_>>(), ["x86", "arm", "riscv64"]);
+    }
+    #[test]
+    fn test_statements() {
+        use crate::{AssignOp, Statement};
+        let input = "srcs = [\"a.c\"]\nfoo { name: \"foo\" }\nsrcs += [\"b.c\"]\nbar {}\n";
+        let bp = BluePrint::parse(input).unwrap();
+        assert_eq!(bp.statements.len(), 4);
+        let Statement::Assignment(first) = &bp.statements[0] else {
+            panic!("not an assignment");
+        };
+        assert_eq!((first.name.as_str(), first.op), ("srcs", AssignOp::Assign));
+        assert_eq!(bp.statements[1], Statement::Module(0));
+        let Statement::Assignment(append) = &bp.statements[2] else {
+            panic!("not an assignment");
+        };
+        assert_eq!(append.op, AssignOp::Append);
+        assert_eq!(append.value.to_value(), Value::Array(vec!["b.c".into()]));
+        let span = append.value_span();
+        assert_eq!(&input[span.start.offset..span.end.offset], "[\"b.c\"]");
+        assert_eq!(append.name_span().start.line, 3);
+        assert_eq!(bp.statements[3], Statement::Module(1));
+        assert_eq!(bp.modules[1].typ, "bar");
+        assert_eq!(bp.variables["srcs"], Value::Array(vec!["a.c".into(), "b.c".into()]));
+    }
+    #[test]
+    fn test_append_expressions() {
+        let input = r#"
+extra_flags = ["-b"]
+common_flags = ["-a"]
+common_flags += extra_flags
+common_flags += ["-c"]
+suffix = "_x"
+name = "lib"
+name += suffix + "_y"
+count = 1
+count += 2
+"#;
+        let bp = BluePrint::parse(input).unwrap();
+        assert_eq!(
+            bp.variables["common_flags"],
+            Value::ConcatExpr(vec![
+                Value::Array(vec!["-a".into()]),
+                Value::Ident("extra_flags".to_string()),
+                Value::Array(vec!["-c".into()]),
+            ])
+        );
+        assert_eq!(
+            bp.variables["name"],
+            Value::ConcatExpr(vec!["lib".into(), Value::Ident("suffix".to_string()), "_y".into()])
+        );
+        assert_eq!(bp.variables["count"], Value::Integer(3));
+        let err = BluePrint::parse("a = true\na += false\n").unwrap_err();
+        assert_eq!(err.to_string(), "2:6: cannot append value to variable `a` of this type");
+    }
+    #[test]
+    fn test_expr_tree() {
+        use crate::expr::ExprKind;
+        use indexmap::IndexMap;
+        let input = "foo {\n    srcs: common + [\"b.c\"],\n    name: prefix + 1,\n}\n";
+        // the variables are not set in the file
+        let (bp, errors) = BluePrint::parse_recovering(input);
+        assert_eq!(errors.len(), 2);
+        let srcs = bp.modules[0].expr("srcs").unwrap();
+        let ExprKind::Add(lhs, rhs) = &srcs.kind else {
+            panic!("not an addition");
+        };
+        assert_eq!(lhs.kind, ExprKind::Variable("common".to_string()));
+        assert!(matches!(&rhs.kind, ExprKind::List(l) if l.len() == 1));
+        let span = srcs.span();
+        assert_eq!(&input[span.start.offset..span.end.offset], "common + [\"b.c\"]");
+        let mut variables = IndexMap::new();
+        let err = srcs.evaluate(&variables).unwrap_err();
+        assert_eq!(err.to_string(), "2:11: variable `common` not found");
+        variables.insert("common".to_string(), Value::Array(vec!["a.c".into()]));
+        variables.insert("prefix".to_string(), "lib".into());
+        assert_eq!(
+            srcs.evaluate(&variables).unwrap(),
+            Value::Array(vec!["a.c".into(), "b.c".into()])
+        );
+        let name = bp.modules[0].expr("name").unwrap();
+        let err = name.evaluate(&variables).unwrap_err();
+        assert_eq!(err.to_string(), "3:11: mismatched types in operator +: string and int64");
+        // literals are folded in the values, as before
+        let bp = BluePrint::parse("foo { name: \"a\" + \"b\" }").unwrap();
+        assert_eq!(bp.modules[0].get_string("name").unwrap(), "ab");
+        assert!(matches!(bp.modules[0].expr("name").unwrap().kind, ExprKind::Add(..)));
+    }
+    #[test]
+    fn test_evaluate() {
+        let input = r#"
+common_srcs = ["a.c"]
+extra = common_srcs + ["b.c"]
+extra += ["c.c"]
+version = 1
+foo {
+    name: "lib" + "foo",
+    srcs: extra + ["x.c"],
+    sdk_version: version + 2,
+    arch: { arm: { srcs: common_srcs } },
+    enabled: select(arch(), { "arm64": false, default: true }),
+}
+"#;
+        let bp = BluePrint::parse(input).unwrap();
+        assert_eq!(bp.modules[0].get_array("srcs"), None);
+        let bp = bp.evaluate().unwrap();
+        let srcs: Vec<Value> = ["a.c", "b.c", "c.c"].into_iter().map(Value::from).collect();
+        assert_eq!(bp.variables["extra"], Value::Array(srcs.clone()));
+        let module = &bp.modules[0];
+        assert_eq!(module.get_string("name").unwrap(), "libfoo");
+        assert_eq!(module.get_array("srcs").unwrap().len(), 4);
+        assert_eq!(module.get_int("sdk_version"), Some(3));
+        let arch = module.get_map("arch").unwrap();
+        let Value::Map(arm) = &arch["arm"] else {
+            panic!("not a map");
+        };
+        assert_eq!(arm["srcs"], Value::Array(srcs[..1].to_vec()));
+        assert!(matches!(module.get("enabled"), Some(Value::Select(_))));
+        // locations are kept
+        assert_eq!(module.value_span("srcs").unwrap().start.line, 8);
+
+        // variables are set before they are referenced, as in blueprint
+        let err = BluePrint::parse("foo {\n    srcs: missing + [\"a.c\"],\n}\n").unwrap_err();
+        assert_eq!(err.to_string(), "2:11: variable `missing` is not set");
+        let (bp, _) = BluePrint::parse_recovering("foo {\n    srcs: missing + [\"a.c\"],\n}\n");
+        let err = bp.evaluate().unwrap_err();
+        assert_eq!(err.to_string(), "2:11: variable `missing` not found");
+        let bp = BluePrint::parse("name = \"foo\"\nfoo { srcs: [\"a.c\"] + name }\n").unwrap();
+        let err = bp.evaluate().unwrap_err();
+        assert_eq!(err.to_string(), "2:13: mismatched types in operator +: list and string");
+        let (_, errors) = BluePrint::parse_recovering("a = b\nb = 1\nfoo { x: undefined_var }\n");
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages, ["1:5: variable `b` is not set", "3:10: variable `undefined_var` is not set"]);
+
+        // properties set on a module are kept when it is evaluated
+        let bp = BluePrint::parse("x = [\"a.c\"]\nfoo { srcs: x }\n").unwrap();
+        let mut module = bp.modules[0].clone();
+        module.set("cflags", Value::Array(vec!["-Wall".into()]));
+        module.remove("srcs");
+        let module = module.resolved(&bp.variables).unwrap();
+        assert_eq!(module.entries().keys().collect::<Vec<_>>(), ["cflags"]);
+        assert_eq!(module.get_array("cflags").unwrap().len(), 1);
+
+        // modules built from values have no location
+        let entries = [("srcs".to_string(), Value::Ident("x".to_string()))].into_iter().collect();
+        let module = Module::new("foo", entries);
+        assert!(!module.expr("srcs").unwrap().span().is_known());
+        let err = module.resolved(&crate::IndexMap::new()).unwrap_err();
+        assert_eq!(err.position(), None);
+        assert_eq!(err.to_string(), "variable `x` not found");
+    }
+    #[test]
+    fn test_defaults() {
+        let input = r#"
+cc_defaults {
+    name: "base_defaults",
+    cflags: ["-base"],
+    host_supported: true,
+    target: { android: { cflags: ["-android"] } },
+}
+cc_defaults {
+    name: "common_defaults",
+    defaults: ["base_defaults"],
+    cflags: ["-common"],
+    host_supported: false,
+    stl: "none",
+}
+cc_library {
+    name: "libfoo",
+    defaults: ["common_defaults"],
+    cflags: ["-foo"],
+    stl: "libc++",
+    target: { android: { srcs: ["android.c"] } },
+}
+"#;
+        let bp = BluePrint::parse(input).unwrap().evaluate().unwrap();
+        let module = bp.with_defaults(bp.module("libfoo").unwrap()).unwrap();
+        assert_eq!(module.get_string("name").unwrap(), "libfoo");
+        assert_eq!(
+            module.get_array("cflags").unwrap(),
+            &vec!["-base".into(), "-common".into(), "-foo".into()]
+        );
+        assert_eq!(module.get_string("stl").unwrap(), "libc++");
+        assert_eq!(module.get_bool("host_supported"), Some(false));
+        let Value::Map(android) = &module.get_map("target").unwrap()["android"] else {
+            panic!("not a map");
+        };
+        assert_eq!(android.keys().collect::<Vec<_>>(), ["srcs", "cflags"]);
+        // properties taken from defaults point to their definition
+        assert_eq!(module.key_span("host_supported").unwrap().start.line, 12);
+
+        let input = "cc_library {\n    name: \"a\",\n    defaults: [\"x_defaults\", \"missing\"],\n}\ncc_defaults { name: \"x_defaults\" }\n";
+        let bp = BluePrint::parse(input).unwrap();
+        let err = bp.with_defaults(&bp.modules[0]).unwrap_err();
+        assert_eq!(err.to_string(), "3:30: \"defaults\" depends on undefined module \"missing\"");
+        let input = "cc_defaults { name: \"a\", defaults: [\"b\"] }\ncc_defaults { name: \"b\", defaults: [\"a\"] }\n";
+        let bp = BluePrint::parse(input).unwrap();
+        let err = bp.with_defaults(&bp.modules[0]).unwrap_err();
+        assert_eq!(err.to_string(), "2:37: defaults cycle: a -> b -> a");
+        let input = "cc_library { name: \"a\", defaults: [\"b\"] }\ncc_library { name: \"b\" }\n";
+        let bp = BluePrint::parse(input).unwrap();
+        let err = bp.with_defaults(&bp.modules[0]).unwrap_err();
+        assert_eq!(err.to_string(), "1:36: module \"b\" is not a defaults module");
+    }
+    #[test]
+    fn test_for_variant() {
+        use crate::Variant;
+        let input = r#"
+cc_library {
+    name: "libfoo",
+    srcs: ["common.c"],
+    stl: "libc++",
+    arch: {
+        arm64: { srcs: ["arm64.c"] },
+        x86: { srcs: ["x86.c"] },
+    },
+    multilib: { lib32: { cflags: ["-m32"] } },
+    target: {
+        android: { shared_libs: ["liblog"], stl: "none" },
+        host: { srcs: ["host.c"] },
+        linux_glibc: { enabled: false },
+        not_windows: { cflags: ["-fPIC"] },
+        android_arm64: { srcs: ["android_arm64.c"] },
+    },
+    codegen: { arm64: { srcs: ["codegen_arm64.c"] } },
+}
+"#;
+        let bp = BluePrint::parse(input).unwrap().evaluate().unwrap();
+        let device = bp.modules[0].for_variant(&Variant::new("android", "arm64", "lib64")).unwrap();
+        let srcs: Vec<Value> = ["common.c", "arm64.c", "android_arm64.c", "codegen_arm64.c"]
+            .into_iter()
+            .map(Value::from)
+            .collect();
+        assert_eq!(device.get_array("srcs").unwrap(), &srcs);
+        assert_eq!(device.get_string("stl").unwrap(), "none");
+        // not_windows only applies to host variants
+        assert_eq!(device.get("cflags"), None);
+        assert_eq!(device.get("target"), None);
+        assert_eq!(device.get("enabled"), None);
+        // the property comes from target.android
+        assert_eq!(device.key_span("shared_libs").unwrap().start.line, 12);
+
+        let host = bp.modules[0].for_variant(&Variant::new("linux_glibc", "x86", "lib32")).unwrap();
+        let srcs: Vec<Value> = ["common.c", "x86.c", "host.c"].into_iter().map(Value::from).collect();
+        assert_eq!(host.get_array("srcs").unwrap(), &srcs);
+        assert_eq!(host.get_array("cflags").unwrap(), &vec!["-m32".into(), "-fPIC".into()]);
+        assert_eq!(host.get_bool("enabled"), Some(false));
+        assert_eq!(host.get_string("stl").unwrap(), "libc++");
+
+        let bp = BluePrint::parse("foo { srcs: [], arch: { arm: { srcs: \"a.c\" } } }").unwrap();
+        let err = bp.modules[0].for_variant(&Variant::new("android", "arm", "lib32")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1:38: cannot apply arch.arm to property \"srcs\": mismatched types in operator +: list and string"
+        );
+    }
+    #[test]
+    fn test_variants() {
+        use crate::Variant;
+        let targets = [
+            Variant::new("android", "arm64", "lib64"),
+            Variant::new("android", "arm", "lib32"),
+            Variant::new("linux_glibc", "x86_64", "lib64"),
+            Variant::new("linux_glibc", "x86", "lib32"),
+        ];
+        let input = r#"
+cc_library {
+    name: "libfoo",
+    host_supported: true,
+    vendor_available: true,
+    recovery_available: true,
+    target: { host: { compile_multilib: "64" } },
+}
+cc_binary {
+    name: "foo",
+    vendor: true,
+    arch: { arm64: { enabled: false } },
+}
+cc_binary_host {
+    name: "foo_host",
+    compile_multilib: "32",
+}
+cc_library {
+    name: "libbar",
+    compile_multilib: "first",
+    target: { android: { enabled: false } },
+}
+cc_defaults { name: "bar_defaults" }
+"#;
+        let bp = BluePrint::parse(input).unwrap().evaluate().unwrap();
+        let names = |m: &Module| -> Vec<String> {
+            m.variants(&targets)
+                .unwrap()
+                .iter()
+                .map(|v| format!("{}_{}_{}", v.os, v.arch, v.image))
+                .collect()
+        };
+        assert_eq!(
+            names(&bp.modules[0]),
+            [
+                "android_arm64_core",
+                "android_arm64_vendor",
+                "android_arm64_recovery",
+                "android_arm_core",
+                "android_arm_vendor",
+                "linux_glibc_x86_64_core",
+            ]
+        );
+        // the first target is disabled
+        assert!(names(&bp.modules[1]).is_empty());
+        assert_eq!(names(&bp.modules[2]), ["linux_glibc_x86_core"]);
+        assert!(names(&bp.modules[3]).is_empty());
+        assert!(names(&bp.modules[4]).is_empty());
+        // java modules are built once per os, prebuilt files for the first
+        // target, and filegroups are not arch specific
+        let input = r#"
+java_library { name: "foo", host_supported: true, compile_multilib: "both" }
+android_app { name: "app" }
+prebuilt_etc { name: "foo.conf" }
+filegroup { name: "srcs" }
+"#;
+        let bp = BluePrint::parse(input).unwrap();
+        let variants = bp.modules[0].variants(&targets).unwrap();
+        assert_eq!(
+            variants,
+            [Variant::new("android", "common", "common"), Variant::new("linux_glibc", "common", "common")]
+        );
+        assert_eq!(names(&bp.modules[1]), ["android_common_core"]);
+        assert_eq!(names(&bp.modules[2]), ["android_arm64_core"]);
+        assert_eq!(bp.modules[3].variants(&targets).unwrap(), [Variant::new("", "", "")]);
+        // image specific blocks apply to their variant
+        let input = "cc_library { vendor_available: true, target: { vendor: { cflags: [\"-DVENDOR\"] } } }";
+        let bp = BluePrint::parse(input).unwrap();
+        let vendor = bp.modules[0].for_variant(&targets[0].with_image("vendor")).unwrap();
+        assert_eq!(vendor.get_array("cflags").unwrap(), &vec!["-DVENDOR".into()]);
+        assert_eq!(bp.modules[0].for_variant(&targets[0]).unwrap().get("cflags"), None);
+    }
+    #[test]
+    fn test_for_linkage() {
+        use crate::{Linkage, Variant};
+        let input = r#"
+cc_library {
+    name: "libfoo",
+    srcs: ["foo.c"],
+    cflags: ["-Wall"],
+    static: { srcs: ["static.c"], cflags: ["-DSTATIC"] },
+    shared: { shared_libs: ["liblog"] },
+    target: {
+        android: {
+            static: { whole_static_libs: ["libbase"] },
+            shared: { shared_libs: ["libcutils"] },
+        },
+    },
+}
+"#;
+        let bp = BluePrint::parse(input).unwrap().evaluate().unwrap();
+        let module = &bp.modules[0];
+        let static_lib = module.for_linkage(Linkage::Static).unwrap();
+        assert_eq!(static_lib.get_array("srcs").unwrap(), &vec!["foo.c".into(), "static.c".into()]);
+        assert_eq!(static_lib.get_array("cflags").unwrap(), &vec!["-Wall".into(), "-DSTATIC".into()]);
+        assert_eq!(static_lib.get("static"), None);
+        assert_eq!(static_lib.get("shared_libs"), None);
+        let device = static_lib.for_variant(&Variant::new("android", "arm64", "lib64")).unwrap();
+        assert_eq!(device.get_array("whole_static_libs").unwrap(), &vec!["libbase".into()]);
+
+        // the order of for_variant and for_linkage does not matter
+        let android = Variant::new("android", "arm64", "lib64");
+        let shared = module.for_variant(&android).unwrap().for_linkage(Linkage::Shared).unwrap();
+        assert_eq!(shared.get_array("shared_libs").unwrap(), &vec!["liblog".into(), "libcutils".into()]);
+        assert_eq!(shared, module.for_linkage(Linkage::Shared).unwrap().for_variant(&android).unwrap());
+        assert_eq!(shared.get_array("srcs").unwrap(), &vec!["foo.c".into()]);
+    }
+    #[test]
+    fn test_soong_config_module_type() {
+        use crate::soong_config::ModuleTypes;
+        use crate::SoongConfig;
+        let declarations = r#"
+soong_config_module_type {
+    name: "acme_cc_defaults",
+    module_type: "cc_defaults",
+    config_namespace: "acme",
+    variables: ["board"],
+    bool_variables: ["feature"],
+    value_variables: ["width"],
+    properties: ["cflags", "target.android.srcs"],
+}
+soong_config_string_variable {
+    name: "board",
+    values: ["soc_a", "soc_b"],
+}
+"#;
+        let input = r#"
+soong_config_module_type_import {
+    from: "device/acme/Android.bp",
+    module_types: ["acme_cc_defaults"],
+}
+acme_cc_defaults {
+    name: "acme_defaults",
+    cflags: ["-DGENERIC"],
+    soong_config_variables: {
+        board: {
+            soc_a: { cflags: ["-DSOC_A"] },
+            conditions_default: { cflags: ["-DSOC_DEFAULT"] },
+        },
+        feature: {
+            target: { android: { srcs: ["feature.c"] } },
+            conditions_default: { cflags: ["-DNO_FEATURE"] },
+        },
+        width: { cflags: ["-DWIDTH=%s"] },
+    },
+}
+"#;
+        let bp = BluePrint::parse(input).unwrap().evaluate().unwrap();
+        let types = ModuleTypes::for_file(&bp, |path| {
+            assert_eq!(path, "device/acme/Android.bp");
+            BluePrint::parse(declarations)
+        })
+        .unwrap();
+        let mut config = SoongConfig::default();
+        config.set("acme", "board", "soc_a").set("acme", "width", "64");
+        let module = &types.apply(&bp, &config).unwrap().modules[1];
+        assert_eq!(module.typ, "cc_defaults");
+        assert_eq!(module.get("soong_config_variables"), None);
+        let cflags: Vec<Value> = ["-DGENERIC", "-DSOC_A", "-DNO_FEATURE", "-DWIDTH=64"]
+            .into_iter()
+            .map(Value::from)
+            .collect();
+        assert_eq!(module.get_array("cflags").unwrap(), &cflags);
+        assert_eq!(module.get("target"), None);
+
+        let mut config = SoongConfig::default();
+        config.set("acme", "board", "soc_b").set("acme", "feature", "true");
+        let module = &types.apply(&bp, &config).unwrap().modules[1];
+        assert_eq!(module.get_array("cflags").unwrap(), &vec!["-DGENERIC".into(), "-DSOC_DEFAULT".into()]);
+        assert!(module.get_map("target").is_some());
+
+        // undeclared values, properties and imports are reported
+        let types = ModuleTypes::declared_in(&BluePrint::parse(declarations).unwrap()).unwrap();
+        let bad = "acme_cc_defaults {\n    soong_config_variables: {\n        board: { soc_c: {} },\n    },\n}\n";
+        let err = types.apply(&BluePrint::parse(bad).unwrap(), &config).unwrap_err();
+        assert_eq!(err.to_string(), "3:18: soong_config_variables.board: unknown value \"soc_c\"");
+        let bad = "acme_cc_defaults {\n    soong_config_variables: {\n        feature: { srcs: [] },\n    },\n}\n";
+        let err = types.apply(&BluePrint::parse(bad).unwrap(), &config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:20: soong_config_variables.feature: property \"srcs\" is not declared in module type \"acme_cc_defaults\""
+        );
+        let bad = "soong_config_module_type_import {\n    from: \"a/Android.bp\",\n    module_types: [\"b\"],\n}\n";
+        let err = ModuleTypes::for_file(&BluePrint::parse(bad).unwrap(), |_| BluePrint::parse(declarations))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:19: soong_config_module_type_import: \"module_types\" module type \"b\" is not declared in \"a/Android.bp\""
+        );
+    }
+    #[test]
+    fn test_product_variables() {
+        use crate::ProductVariables;
+        let variables = ProductVariables::parse(
+            r#"{
+    "Platform_sdk_version": 34,
+    "Debuggable": true,
+    "Eng": false,
+    "Device_name": "acme",
+    "Unbundled_build_apps": null,
+    "VendorVars": {"acme": {"board": "soc_a"}}
+}"#,
+        )
+        .unwrap();
+        assert_eq!(variables.get("Platform_sdk_version"), Some(&Value::Integer(34)));
+        assert_eq!(variables.get("Unbundled_build_apps"), None);
+        assert_eq!(variables.soong_config().get("acme", "board"), Some("soc_a"));
+
+        let input = r#"
+cc_library {
+    name: "foo",
+    cflags: ["-DFOO"],
+    product_variables: {
+        debuggable: { cflags: ["-DDEBUG"] },
+        eng: { cflags: ["-DENG"] },
+        platform_sdk_version: { cflags: ["-DSDK=%d"] },
+        device_name: { cflags: ["-DDEVICE=%s"], enabled: false },
+        unbundled_build_apps: { cflags: ["-DUNBUNDLED"] },
+    },
+}
+"#;
+        let bp = BluePrint::parse(input).unwrap().evaluate().unwrap();
+        let module = bp.modules[0].with_product_variables(&variables).unwrap();
+        assert_eq!(module.get("product_variables"), None);
+        let cflags: Vec<Value> = ["-DFOO", "-DDEBUG", "-DSDK=34", "-DDEVICE=acme"]
+            .into_iter()
+            .map(Value::from)
+            .collect();
+        assert_eq!(module.get_array("cflags").unwrap(), &cflags);
+        assert_eq!(module.get_bool("enabled"), Some(false));
+
+        let bad = "cc_library {\n    product_variables: { eng: [] },\n}\n";
+        let err = BluePrint::parse(bad).unwrap().modules[0]
+            .with_product_variables(&variables)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "2:31: product_variables.eng must be a map");
+        let err = ProductVariables::parse("{\"Eng\": tru}").unwrap_err();
+        assert!(matches!(err, crate::Error::Syntax { .. }));
+        let err = ProductVariables::parse("\n  [1, 2]\n").unwrap_err();
+        assert_eq!(err.to_string(), "2:3: product variables must be a JSON object");
+        let input = "\n  [1, 2]\n";
+        assert!(crate::Diagnostic::from_error(&err, input).render(input).contains("^^^^^^"));
+    }
+    #[test]
+    fn test_glob() {
+        use crate::glob::MemoryFileSystem;
+        use std::path::Path;
+        let fs: MemoryFileSystem = [
+            "lib/Android.bp",
+            "lib/src/a.cpp",
+            "lib/src/b.h",
+            "lib/src/XmlRpc.cpp",
+            "lib/src/deep/er/c.cpp",
+            "lib/src/test/t.cpp",
+            "lib/src/other/Android.bp",
+            "lib/src/other/o.cpp",
+            "lib/include/x.h",
+        ]
+        .into_iter()
+        .collect();
+        let bp = BluePrint::parse(include_str!("../fixtures/example.bp")).unwrap();
+        let srcs = bp.modules[0].paths("srcs", Path::new("lib"), &fs).unwrap();
+        assert_eq!(srcs, ["src/XmlRpc.cpp", "src/a.cpp", "src/deep/er/c.cpp", "src/test/t.cpp"]);
+
+        let input = r#"
+cc_library {
+    srcs: [
+        "src/[a-z]*.cpp",
+        "src/**",
+        ":libfoo_srcs",
+        "include/x.h",
+    ],
+    exclude_srcs: ["**/*.h"],
+    exclude_dirs: ["src/deep"],
+}
+"#;
+        let bp = BluePrint::parse(input).unwrap();
+        let srcs = bp.modules[0].paths("srcs", Path::new("lib"), &fs).unwrap();
+        assert_eq!(srcs, ["src/a.cpp", "src/XmlRpc.cpp", "src/test/t.cpp", ":libfoo_srcs"]);
+
+        for (path, message) in [
+            ("../x.c", "3:22: path \"../x.c\" is outside the module directory"),
+            ("/x.c", "3:22: path \"/x.c\" must be relative to the module directory"),
+            ("src/a**.c", "3:22: \"**\" must be a full path component in \"src/a**.c\""),
+            ("src/[a-.c", "3:22: malformed character class in \"src/[a-.c\""),
+            ("src/[]a].c", "3:22: malformed character class in \"src/[]a].c\""),
+            ("src/missing.c", "3:22: source path \"src/missing.c\" does not exist"),
+        ] {
+            let input = format!("cc_library {{\n    srcs: [\n        \"src/a.cpp\", \"{}\",\n    ],\n}}\n", path);
+            let bp = BluePrint::parse(&input).unwrap();
+            let err = bp.modules[0].paths("srcs", Path::new("lib"), &fs).unwrap_err();
+            assert_eq!(err.to_string(), message);
+        }
+        // missing files fail the property, even when they are excluded
+        let input = "cc_library { srcs: [\"src/a.cpp\", \"b.c\"], exclude_srcs: [\"b.c\"] }";
+        let err = BluePrint::parse(input).unwrap().modules[0]
+            .paths("srcs", Path::new("lib"), &fs)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "1:34: source path \"b.c\" does not exist");
+    }
+    #[test]
+    fn test_module_references() {
+        use crate::glob::MemoryFileSystem;
+        use crate::{ModuleReference, SourcePath};
+        use std::path::Path;
+        assert_eq!(ModuleReference::parse("a.c"), None);
+        assert_eq!(
+            ModuleReference::parse(":gen{out.h}"),
+            Some(ModuleReference {
+                name: "gen".to_string(),
+                tag: Some("out.h".to_string())
+            })
+        );
+        let fs: MemoryFileSystem = ["foo/main.c", "foo/common/a.c", "foo/common/b.c", "foo/tool.py"]
+            .into_iter()
+            .collect();
+        let input = r#"
+filegroup {
+    name: "common_srcs",
+    srcs: ["common/*.c"],
+    exclude_srcs: ["common/b.c"],
+}
+filegroup {
+    name: "all_srcs",
+    srcs: [":common_srcs", "main.c"],
+}
+genrule {
+    name: "gen_headers",
+    tool_files: ["tool.py"],
+    out: ["out.h", "other.h"],
+}
+cc_binary {
+    name: "foo",
+    srcs: [":all_srcs", ":gen_headers{out.h}", ":libbar{.jar}", ":gen_headers"],
+}
+java_library {
+    name: "libbar",
+}
+"#;
+        let bp = BluePrint::parse(input).unwrap();
+        let dir = Path::new("foo");
+        let paths = bp.source_paths(&bp.modules[3], "srcs", dir, &fs).unwrap();
+        let generated = |path: &str| SourcePath::Generated {
+            module: "gen_headers".to_string(),
+            path: path.to_string(),
+        };
+        assert_eq!(
+            paths,
+            [
+                SourcePath::File("foo/common/a.c".to_string()),
+                SourcePath::File("foo/main.c".to_string()),
+                generated("out.h"),
+                SourcePath::Module {
+                    module: "libbar".to_string(),
+                    tag: Some(".jar".to_string())
+                },
+                generated("other.h"),
+            ]
+        );
+        let paths = bp.source_paths(&bp.modules[2], "tool_files", dir, &fs).unwrap();
+        assert_eq!(paths, [SourcePath::File("foo/tool.py".to_string())]);
+
+        // modules of other directories are found by `find`
+        let other = BluePrint::parse("filegroup { name: \"x\", srcs: [\"x.c\"] }").unwrap();
+        let fs: MemoryFileSystem = ["bar/x.c"].into_iter().collect();
+        let module = BluePrint::parse("cc_test { data: [\":x\"] }").unwrap().modules.remove(0);
+        let paths = module
+            .source_paths_with("data", dir, &fs, |name| match name {
+                "x" => Some((&other.modules[0], Path::new("bar"))),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(paths, [SourcePath::File("bar/x.c".to_string())]);
+
+        for (srcs, message) in [
+            ("\":missing\"", "4:19: \"srcs\" depends on undefined module \"missing\""),
+            ("\":gen_headers{none.h}\"", "4:19: module \"gen_headers\" has no output tag \"none.h\""),
+            ("\":all_srcs{.tag}\"", "4:19: module \"all_srcs\" has no output tag \".tag\""),
+            ("\":foo\"", "4:19: reference cycle: foo -> foo"),
+            ("\":all_srcs\"", "1:38: reference cycle: foo -> all_srcs -> foo"),
+        ] {
+            let module = format!("filegroup {{\n    name: \"foo\",\n    srcs: [\n        \"main.c\", {},\n    ],\n}}\n", srcs);
+            let module = BluePrint::parse(&module).unwrap().modules.remove(0);
+            let fs: MemoryFileSystem = ["foo/main.c"].into_iter().collect();
+            let all_srcs = BluePrint::parse("filegroup { name: \"all_srcs\", srcs: [\":foo\"] }").unwrap();
+            let err = module
+                .source_paths_with("srcs", dir, &fs, |name| match name {
+                    "foo" => Some((&module, dir)),
+                    "all_srcs" => Some((&all_srcs.modules[0], dir)),
+                    "gen_headers" => bp.module(name).map(|m| (m, dir)),
+                    _ => None,
+                })
+                .unwrap_err();
+            assert_eq!(err.to_string(), message);
+        }
+    }
+    #[test]
     fn test_nested_dict() {
         let input = r#"
         rust_test_host {
@@ -177,8 +867,8 @@ mod tests {
         }        
         */"#;
         let output = BluePrint::parse(input);
-        if output.is_err() {
-            println!("Error: {}", output.unwrap_err());
+        if let Err(e) = output {
+            println!("Error: {}", e);
             panic!("Error in parsing");
         }
     }
@@ -241,15 +931,15 @@ mod tests {
         display_error(input, &output);
         assert!(output.is_ok());
     }
-    fn display_error<T>(input: &str, output: &Result<(&str, T), Err<VerboseError<&str>>>) -> () {
+    fn display_error<T>(input: &str, output: &Result<(&str, T), Err<VerboseError<&str>>>) {
         if let Err(e) = output {
-            println!("Error: {}", format_err(input, e.clone()));
+            println!("Error: {}", crate::Error::syntax(input, e.clone()));
         }
     }
     #[test]
     fn test_expr() {
         let input = r#""abc" + "def""#;
-        let output = parse_expr(input);
+        let output = parse_expr(input).map(|(rest, e)| (rest, e.to_value()));
         display_error(input, &output);
         assert!(output.is_ok());
         assert!(output.as_ref().unwrap().0.is_empty());
@@ -258,7 +948,7 @@ mod tests {
     #[test]
     fn test_expr_array() {
         let input = r#"["abc", "def"] + [ "ghi" ]"#;
-        let output = parse_expr(input);
+        let output = parse_expr(input).map(|(rest, e)| (rest, e.to_value()));
         display_error(input, &output);
         assert!(output.is_ok());
         assert!(output.as_ref().unwrap().0.is_empty());
@@ -267,7 +957,7 @@ mod tests {
     #[test]
     fn test_expr_ident() {
         let input = r#"ident + [ "ghi" ]"#;
-        let output = parse_expr(input);
+        let output = parse_expr(input).map(|(rest, e)| (rest, e.to_value()));
         display_error(input, &output);
         assert!(output.is_ok());
         assert!(output.as_ref().unwrap().0.is_empty());
@@ -282,7 +972,7 @@ mod tests {
     #[test]
     fn test_expr_value() {
         let input = r#"123"#;
-        let output = parse_expr(input);
+        let output = parse_expr(input).map(|(rest, e)| (rest, e.to_value()));
         display_error(input, &output);
         assert!(output.is_ok());
         assert!(output.as_ref().unwrap().0.is_empty());
@@ -290,6 +980,39 @@ mod tests {
             output.unwrap().1
                 == Value::Integer(123));
     }
+    #[test]
+    fn test_expr_int_and_map() {
+        assert_eq!(parse_expr("1 + 2 + -4").unwrap().1.to_value(), Value::Integer(-1));
+        let input = r#"{a: ["x"], c: {d: 1}} + {a: ["y"], b: "z", c: {d: 2, e: true}}"#;
+        let Value::Map(map) = parse_expr(input).unwrap().1.to_value() else {
+            panic!("not a map");
+        };
+        assert_eq!(map.len(), 3);
+        assert_eq!(map["a"], Value::Array(vec!["x".into(), "y".into()]));
+        assert_eq!(map["b"], "z".into());
+        let Value::Map(c) = &map["c"] else {
+            panic!("not a map");
+        };
+        assert_eq!(c["d"], Value::Integer(3));
+        assert_eq!(c["e"], Value::Boolean(true));
+        let bp = BluePrint::parse("x = {a: 1}\nx += {a: 2, b: 3}\n").unwrap();
+        let Value::Map(x) = &bp.variables["x"] else {
+            panic!("not a map");
+        };
+        assert_eq!((&x["a"], &x["b"]), (&Value::Integer(3), &Value::Integer(3)));
+    }
+    #[test]
+    fn test_expr_mismatched_types() {
+        let err = BluePrint::parse("foo {\n    a: 1 + \"b\",\n}\n").unwrap_err();
+        assert_eq!(err.to_string(), "2:8: mismatched types in operator +: int64 and string");
+        let err = BluePrint::parse("x = {a: 1} + {a: [\"b\"]}").unwrap_err();
+        assert_eq!(err.to_string(), "1:5: mismatched types in operator +: int64 and list");
+        let err = BluePrint::parse("foo {\n    a: [true + false],\n}\n").unwrap_err();
+        assert_eq!(err.to_string(), "2:8: operator + is not supported on bool values");
+        let (bp, errors) = BluePrint::parse_recovering("foo { a: [\"a\"] + \"b\", b: 1 }");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(bp.modules[0].get_int("b"), Some(1));
+    }
     // found in platform_testing/tests/health/scenarios/tests/Android.bp
     #[test]
     fn test_complicated_concat() {
@@ -301,7 +1024,7 @@ mod tests {
         "$$out_dir/$$assets_dir/$${fname//.textpb/.pb}) ; then " +
         "echo \"\x1b[0;31mFailed to parse profile $$file. See above for errors.\x1b[0m\" " +
         "&& exit 1 ; fi ; done && jar cf $(out) -C $$(dirname $(out)) $$assets_dir""#;
-        let output = parse_expr(input);
+        let output = parse_expr(input).map(|(rest, e)| (rest, e.to_value()));
         display_error(input, &output);
         assert!(output.is_ok());
         assert!(output.as_ref().unwrap().0.is_empty());
@@ -347,7 +1070,7 @@ mod tests {
     #[test]
     fn test_function() {
         let input = r#"method("ss")"#;
-        let output = parse_expr(input);
+        let output = parse_expr(input).map(|(rest, e)| (rest, e.to_value()));
         display_error(input, &output);
         assert!(output.is_ok());
         assert_eq!(output.unwrap().1, Value::Function(Function {
@@ -357,14 +1080,52 @@ mod tests {
 
     }
     #[test]
-    fn test_aosp_db() {
+    fn test_spans() {
+        let input = r#"// header
+var = ["a"]
+var += ["b"]
+cc_library {
+    name: "libfoo",
+    srcs: ["a.c",
+        "b.c"],
+    target: { host: { cflags: [] } },
+}
+"#;
+        let bp = BluePrint::parse(input).unwrap();
+        let pos = |offset, line, column| crate::Position { offset, line, column };
+        let m = &bp.modules[0];
+        assert_eq!(m.type_span().start, pos(35, 4, 1));
+        assert_eq!(m.type_span().end, pos(45, 4, 11));
+        assert_eq!(m.close_span().start, pos(140, 9, 1));
+        assert_eq!(m.span().end, pos(141, 9, 2));
+        assert_eq!(m.key_span("name").unwrap().start, pos(52, 5, 5));
+        let srcs = m.value_span("srcs").unwrap();
+        assert_eq!(srcs.start, pos(78, 6, 11));
+        assert_eq!(srcs.end, pos(100, 7, 15));
+        assert_eq!(&input[srcs.start.offset..srcs.end.offset], "[\"a.c\",\n        \"b.c\"]");
+        let target = m.get_map("target").unwrap();
+        assert_eq!(&input[target.span().start.offset..target.span().end.offset], "{ host: { cflags: [] } }");
+        assert_eq!(target.key_span("host").unwrap().start, pos(116, 8, 15));
+        let defs = bp.variable_spans("var");
+        assert_eq!(defs.len(), 2);
+        assert_eq!(defs[0].key.start, pos(10, 2, 1));
+        assert_eq!(defs[1].value.start, pos(29, 3, 8));
+        assert_eq!(defs[1].value.end, pos(34, 3, 13));
+        assert!(bp.variable_spans("nope").is_empty());
+        // locations are left out of the debug output
+        let debug = format!("{:?}", bp.modules[0].get("target").unwrap());
+        assert_eq!(debug, "Map(Map({\"host\": Map(Map({\"cflags\": Array([])}, ..))}, ..))");
+    }
+    /// all the Android.bp files of the test database, as (path, contents)
+    fn aosp_db() -> Vec<(String, String)> {
         // generate tarball from aosp tree
         // fd -g Android.bp | tar cJf ../rs-bp/src/test_db.tar.xz -T -
         let data = include_bytes!("test_db.tar.xz");
+        assert!(
+            !data.starts_with(b"version https://git-lfs.github.com/spec/"),
+            "src/test_db.tar.xz is a git-lfs pointer, run `git lfs pull` to fetch it"
+        );
         let mut archive = tar::Archive::new(liblzma::read::XzDecoder::new(&data[..]));
-        let mut count = 0;
-        let mut bytes = 0;
-        let mut num_errors = 0;
         let mut all_bp = Vec::new();
         // first decompress in memory to avoid disk IO for measuring performance
         for entry in archive.entries().unwrap() {
@@ -372,16 +1133,24 @@ mod tests {
             let mut entry_data = std::io::BufReader::new(entry);
             let mut contents = String::new();
             entry_data.read_to_string(&mut contents).unwrap();
-            bytes += contents.len();
             all_bp.push((format!("{:?}", entry_data.into_inner().path().unwrap()), contents));
         }
+        all_bp
+    }
+    #[test]
+    #[ignore = "needs git lfs pull"]
+    fn test_aosp_db() {
+        let all_bp = aosp_db();
+        let mut count = 0;
+        let bytes: usize = all_bp.iter().map(|(_, c)| c.len()).sum();
+        let mut num_errors = 0;
         let now = std::time::Instant::now();
         for (path, contents) in all_bp {
             let output = BluePrint::parse(&contents);
-            if output.is_err() {
+            if let Err(e) = output {
                 println!("Error for file: {:?}", path);
                 println!("File content: {}", contents);
-                println!("Error: {}", output.unwrap_err());
+                println!("Error: {}", e);
                 num_errors += 1;
             }
             count += 1;
@@ -390,4 +1159,480 @@ mod tests {
         println!("{} files ({} bytes) parsed in {:.3}s {}MB/s", count, bytes, elapsed, bytes as f32 / elapsed / 1024.0 / 1024.0);
         assert_eq!(num_errors, 0);
     }
+    #[test]
+    fn test_cst_roundtrip() {
+        let inputs = [
+            include_str!("../fixtures/example.bp"),
+            "",
+            "// only a comment",
+            "/* a */ var = [\"a\", /* b */ \"b\",] + other // c\n",
+            "var += \"a\\\"b\\x1b\\033\" +\n  \"c\"\n",
+            "aidl_interface(name = \"foo\", srcs = [\"a\"])\n",
+            "m { a: method(\"x\", 1,), b: { c: -1, \"d\": true }, }\n\n",
+        ];
+        for input in inputs {
+            let file = crate::cst::File::parse(input).unwrap();
+            assert_eq!(file.to_string(), input);
+        }
+    }
+    #[test]
+    fn test_cst_content() {
+        use crate::cst::{Expr, File, Item, TokenKind};
+        let input = "// header\nfoo {\n    // the name\n    name: \"a\\tb\",\n}\n";
+        let file = File::parse(input).unwrap();
+        assert_eq!(file.items.len(), 1);
+        let Item::Module(module) = &file.items[0] else {
+            panic!("not a module");
+        };
+        assert_eq!(module.typ.leading.comments().collect::<Vec<_>>(), ["// header"]);
+        assert_eq!(module.typ.text, "foo");
+        let property = &module.properties[0];
+        assert_eq!(property.name.leading.comments().collect::<Vec<_>>(), ["// the name"]);
+        let Expr::Literal(value) = &property.value else {
+            panic!("not a literal");
+        };
+        assert_eq!(value.kind, TokenKind::String);
+        assert_eq!(value.text, "\"a\\tb\"");
+        assert!(property.comma.is_some());
+        assert!(File::parse("foo { name: }").is_err());
+        assert!(File::parse("foo { name: \"a\" } }").is_err());
+    }
+    #[test]
+    fn test_select() {
+        use crate::{Condition, Select, SelectCase, SelectPattern};
+        let input = r#"
+cc_library {
+    name: "libfoo",
+    srcs: ["common.c"] + select(soong_config_variable("my_namespace", "my_var"), {
+        "a": ["a.c"],
+        // a comment
+        default: [],
+    }) + select(arch(), {
+        "arm64": ["arm64.c"],
+        default: [],
+    }),
+    cflags: select((os(), arch()), {
+        ("android", "arm64"): ["-DA"],
+        (default, "x86_64"): ["-DB"],
+        (any @ my_os, any): ["-D" + my_os],
+        default: unset,
+    }),
+    enabled: select(release_flag("RELEASE_FOO"), {
+        true: true,
+        false: false,
+    }),
+}
+"#;
+        let bp = BluePrint::parse(input).unwrap();
+        let module = &bp.modules[0];
+        let Some(Value::ConcatExpr(srcs)) = module.get("srcs") else {
+            panic!("srcs is not a concat expression");
+        };
+        assert_eq!(srcs.len(), 3);
+        assert_eq!(
+            srcs[1],
+            Value::Select(Select {
+                conditions: vec![Condition {
+                    name: "soong_config_variable".to_string(),
+                    args: vec!["my_namespace".to_string(), "my_var".to_string()],
+                }],
+                cases: vec![
+                    SelectCase {
+                        patterns: vec![SelectPattern::String("a".to_string())],
+                        value: Some(Value::Array(vec!["a.c".into()])),
+                    },
+                    SelectCase {
+                        patterns: vec![SelectPattern::Default],
+                        value: Some(Value::Array(vec![])),
+                    },
+                ],
+            })
+        );
+        let Some(Value::Select(cflags)) = module.get("cflags") else {
+            panic!("cflags is not a select");
+        };
+        assert_eq!(cflags.conditions.len(), 2);
+        assert_eq!(
+            cflags.cases[2].patterns,
+            [
+                SelectPattern::Any(Some("my_os".to_string())),
+                SelectPattern::Any(None)
+            ]
+        );
+        assert_eq!(cflags.cases[3].value, None);
+        let Some(Value::Select(enabled)) = module.get("enabled") else {
+            panic!("enabled is not a select");
+        };
+        assert_eq!(enabled.cases[1].patterns, [SelectPattern::Boolean(false)]);
+
+        let file = crate::cst::File::parse(input).unwrap();
+        assert_eq!(file.to_string(), input);
+
+        for input in [
+            "foo { a: select(arch(), { \"arm\" [] }) }",
+            "foo { a: select(arch, { default: [] }) }",
+            "foo { a: select(arch(), { any @: [] }) }",
+            "foo { a: select((), { default: [] }) }",
+            "foo { a: select((arch(), os()), { \"x\": 1, (\"a\", \"b\", \"c\"): 2 }) }",
+            "foo { a: select(arch(), { default: 1, default: 2 }) }",
+        ] {
+            assert!(BluePrint::parse(input).is_err(), "{}", input);
+            assert!(crate::cst::File::parse(input).is_err(), "{}", input);
+        }
+        // the number of patterns and the default case are checked at parse time
+        let input = "foo { a: select((arch(), os()), { (\"arm\", default): 1, \"x\": 2 }) }";
+        let err = BluePrint::parse(input).unwrap_err();
+        assert_eq!(err.message(), "syntax error in select case with one pattern per condition");
+        assert_eq!(err.position().unwrap().column, 56);
+        let err = crate::cst::File::parse(input).unwrap_err();
+        assert_eq!(err.position().unwrap().column, 56);
+        let input = "foo { a: select(arch(), { default: 1, default: 2 }) }";
+        let err = BluePrint::parse(input).unwrap_err();
+        assert_eq!(err.message(), "syntax error in select with a single default case");
+        assert_eq!(err.position().unwrap().column, 39);
+    }
+    #[test]
+    fn test_select_configuration() {
+        use crate::{Configuration, ProductVariables, Variant};
+        let input = r#"
+cc_library {
+    name: "libfoo",
+    srcs: ["common.c"] + select(soong_config_variable("acme", "board"), {
+        "soc_a": ["soc_a.c"],
+        default: [],
+    }) + select(arch(), {
+        "arm64": ["arm64.c"],
+        default: unset,
+    }),
+    cflags: select((os(), release_flag("RELEASE_FOO")), {
+        ("android", true): ["-DFOO"],
+        (any @ my_os, any): ["-D" + my_os],
+        (default, default): [],
+    }),
+    stl: select(product_variable("debuggable"), {
+        true: "libc++",
+        default: unset,
+    }),
+    target: {
+        android: {
+            cflags: select(soong_config_variable("acme", "feature"), {
+                true: ["-DFEATURE"],
+                false: [],
+            }),
+        },
+    },
+}
+"#;
+        let bp = BluePrint::parse(input).unwrap().evaluate().unwrap();
+        let mut config = Configuration::new(&Variant::new("android", "arm64", "lib64"));
+        config.soong_config.set("acme", "board", "soc_a").set("acme", "feature", "true");
+        config.release_flags.insert("RELEASE_FOO".to_string(), Value::Boolean(true));
+        config.product_variables = ProductVariables::parse("{\"Debuggable\": true}").unwrap();
+        let module = bp.modules[0].with_configuration(&config).unwrap();
+        let srcs: Vec<Value> = ["common.c", "soc_a.c", "arm64.c"].into_iter().map(Value::from).collect();
+        assert_eq!(module.get_array("srcs").unwrap(), &srcs);
+        assert_eq!(module.get_array("cflags").unwrap(), &vec!["-DFOO".into()]);
+        assert_eq!(module.get_string("stl").unwrap(), "libc++");
+        let android = module.get_map("target").unwrap().get("android").unwrap();
+        let Value::Map(android) = android else {
+            panic!("target.android is not a map");
+        };
+        assert_eq!(android.get("cflags"), Some(&Value::Array(vec!["-DFEATURE".into()])));
+
+        // unset operands are skipped, unset properties removed, bindings substituted
+        let mut config = Configuration::new(&Variant::new("linux_glibc", "x86_64", "lib64"));
+        config.soong_config.set("acme", "feature", "false");
+        config.release_flags.insert("RELEASE_FOO".to_string(), Value::Boolean(false));
+        let module = bp.modules[0].with_configuration(&config).unwrap();
+        assert_eq!(module.get_array("srcs").unwrap(), &vec!["common.c".into()]);
+        assert_eq!(module.get_array("cflags").unwrap(), &vec!["-Dlinux_glibc".into()]);
+        assert_eq!(module.get("stl"), None);
+        assert_eq!(module.key_span("stl"), None);
+
+        // a select without a matching case nor a default is reported
+        config.soong_config = Default::default();
+        let err = bp.modules[0].with_configuration(&config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "22:21: no case of the select on soong_config_variable(\"acme\", \"feature\") matches unset, and it has no default"
+        );
+        let bad = "foo { a: select(variant(), { default: [] }) }";
+        let err = BluePrint::parse(bad).unwrap().modules[0].with_configuration(&config).unwrap_err();
+        assert_eq!(err.to_string(), "1:10: wrong number of arguments to variant()");
+        let bad = "foo { a: select(image(), { default: [] }) }";
+        let err = BluePrint::parse(bad).unwrap().modules[0].with_configuration(&config).unwrap_err();
+        assert_eq!(err.to_string(), "1:10: unknown select condition image()");
+
+        // variant() conditions match the variations of the variant
+        let input = r#"foo { srcs: select(variant("image"), { "vendor": ["vendor.c"], default: ["core.c"] }) }"#;
+        let bp = BluePrint::parse(input).unwrap();
+        let variant = Variant::new("android", "arm64", "lib64");
+        let config = Configuration::new(&variant.with_image("vendor"));
+        let module = bp.modules[0].with_configuration(&config).unwrap();
+        assert_eq!(module.get_array("srcs").unwrap(), &vec!["vendor.c".into()]);
+        let module = bp.modules[0].with_configuration(&Configuration::new(&variant)).unwrap();
+        assert_eq!(module.get_array("srcs").unwrap(), &vec!["core.c".into()]);
+    }
+    #[test]
+    fn test_raw_string() {
+        use crate::cst::{Expr, File, Item, TokenKind};
+        use crate::expr::ExprKind;
+        let input = "genrule {\n    cmd: `sed -e \"s/\\\\//\" $(in)\n        > $(out)`,\n}\n";
+        let bp = BluePrint::parse(input).unwrap();
+        assert_eq!(
+            bp.modules[0].get_string("cmd").unwrap(),
+            "sed -e \"s/\\\\//\" $(in)\n        > $(out)"
+        );
+        assert!(matches!(
+            bp.modules[0].expr("cmd").unwrap().kind,
+            ExprKind::String { raw: true, .. }
+        ));
+        let file = File::parse(input).unwrap();
+        assert_eq!(file.to_string(), input);
+        let Item::Module(module) = &file.items[0] else {
+            panic!("not a module");
+        };
+        let Expr::Literal(value) = &module.properties[0].value else {
+            panic!("not a literal");
+        };
+        assert_eq!(value.kind, TokenKind::RawString);
+    }
+    #[test]
+    #[ignore = "needs git lfs pull"]
+    fn test_aosp_db_cst_roundtrip() {
+        let all_bp = aosp_db();
+        let mut num_errors = 0;
+        for (path, contents) in all_bp {
+            match crate::cst::File::parse(&contents) {
+                Ok(file) if file.to_string() == contents => {}
+                Ok(_) => {
+                    println!("Round-trip mismatch for file: {:?}", path);
+                    num_errors += 1;
+                }
+                Err(e) => {
+                    println!("Error for file: {:?}", path);
+                    println!("Error: {}", e);
+                    num_errors += 1;
+                }
+            }
+        }
+        assert_eq!(num_errors, 0);
+    }
+    #[test]
+    fn test_syntax_error() {
+        let input = "foo {\n    name: \"a\"\n    srcs: [],\n}\n";
+        match BluePrint::parse(input).unwrap_err() {
+            crate::Error::Syntax {
+                file,
+                position,
+                expected,
+                context,
+                ..
+            } => {
+                assert_eq!(file, None);
+                assert_eq!((position.line, position.column), (3, 5));
+                assert_eq!(expected.as_deref(), Some("'}'"));
+                assert_eq!(context, ["module", "blueprint"]);
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+        let err = BluePrint::parse("foo {\n    name: [\"a\",\n}").unwrap_err();
+        assert!(err.to_string().starts_with("3:1: syntax error, expected ']' in array\n"));
+        assert!(crate::cst::File::parse("foo {\n    name: [\"a\",\n}").is_err());
+    }
+    #[test]
+    fn test_semantic_error() {
+        let err = BluePrint::parse("x = 1\nx += \"a\"\n").unwrap_err();
+        assert!(matches!(err, crate::Error::Semantic { .. }));
+        assert_eq!(err.to_string(), "2:6: cannot append value to integer variable `x`");
+        let err = BluePrint::parse("y += 1").unwrap_err();
+        assert_eq!(err.to_string(), "1:1: modified non-existent variable `y` with +=");
+    }
+    #[test]
+    fn test_assignment_rules() {
+        let input = "x = 1
+foo { count: x }
+x = 2
+x += 3
+";
+        let (bp, errors) = BluePrint::parse_recovering(input);
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "3:1: variable `x` is already set",
+                "4:1: modified variable `x` with += after referencing it",
+            ]
+        );
+        assert_eq!(errors[0].notes()[0].0.start.line, 1);
+        assert_eq!(errors[0].notes()[0].1, "previous assignment here");
+        assert_eq!(errors[1].notes()[0].0.start.line, 2);
+        assert_eq!(errors[1].notes()[0].1, "referenced here");
+        assert_eq!(bp.variables["x"], Value::Integer(1));
+        // a variable referenced by its own += is referenced before it is modified
+        let err = BluePrint::parse("x = [\"a\"]\nx += x\n").unwrap_err();
+        assert_eq!(err.to_string(), "2:1: modified variable `x` with += after referencing it");
+        // referencing another variable, or extending before referencing, is fine
+        let input = "x = [\"a\"]\nx += [\"b\"]\ny = x\nfoo { srcs: x + y }\n";
+        assert!(BluePrint::parse(input).is_ok());
+        // names bound by `any @ x` are not references to the variable `x`
+        let input = r#"x = ["a"]
+foo { cflags: select(arch(), { any @ x: ["-D" + x], default: [] }) }
+x += ["b"]
+"#;
+        let bp = BluePrint::parse(input).unwrap();
+        assert!(bp.modules[0].expr("cflags").unwrap().references().is_empty());
+        assert_eq!(bp.variables["x"], Value::Array(vec!["a".into(), "b".into()]));
+    }
+    #[test]
+    fn test_byte_order_mark() {
+        let input = "\u{feff}foo {\n    name: \"foo\",\n}\nbar {}\n";
+        let (bp, errors) = BluePrint::parse_recovering(input);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(bp.modules.len(), 2);
+        assert_eq!(bp.modules[0].get_string("name").unwrap(), "foo");
+        let file = crate::cst::File::parse(input).unwrap();
+        assert_eq!(file.to_string(), input);
+        assert_eq!(crate::cst::File::parse("\u{feff}").unwrap().to_string(), "\u{feff}");
+        let name = bp.modules[0].value_span("name").unwrap();
+        assert_eq!(name.start.column, 11);
+        assert_eq!(&input[name.start.offset..name.end.offset], "\"foo\"");
+        assert_eq!(bp.modules[0].type_span().start.column, 1);
+
+        // diagnostics point at the input, byte order mark included
+        let input = "\u{feff}x = \"ééé\" }\n";
+        let err = BluePrint::parse(input).unwrap_err();
+        assert_eq!(err.position().unwrap().offset, 16);
+        let diagnostic = crate::Diagnostic::from_error(&err, input);
+        assert_eq!(
+            diagnostic.render(input),
+            "error: syntax error in define\n --> <input>:1:11\n  |\n1 | x = \"ééé\" }\n  |           ^\n"
+        );
+    }
+    #[test]
+    fn test_duplicate_keys() {
+        use crate::Diagnostic;
+        let input = "foo {\n    srcs: [\"a.c\"],\n    arch: {\n        arm: {},\n        arm: {},\n    },\n    srcs: [\"b.c\"],\n}\n";
+        let (_, errors) = BluePrint::parse_recovering(input);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].to_string(), "5:9: property \"arch.arm\" already defined");
+        assert_eq!(errors[1].to_string(), "7:5: property \"srcs\" already defined");
+        assert_eq!(errors[1].notes()[0].0.start.line, 2);
+        assert_eq!(
+            Diagnostic::from_error(&errors[1], input).render(input),
+            r#"error: property "srcs" already defined
+ --> <input>:7:5
+  |
+7 |     srcs: ["b.c"],
+  |     ^^^^
+note: previous definition here
+ --> <input>:2:5
+  |
+2 |     srcs: ["a.c"],
+  |     ^^^^
+"#
+        );
+
+        let bp = BluePrint::parse_lenient(input).unwrap();
+        let module = &bp.modules[0];
+        assert_eq!(module.get_array("srcs"), Some(&vec!["a.c".into()]));
+        assert_eq!(
+            module.duplicates().collect::<Vec<_>>(),
+            [("srcs", &Value::Array(vec!["b.c".into()]))]
+        );
+        assert_eq!(module.get_map("arch").unwrap().duplicates().count(), 1);
+    }
+    #[test]
+    fn test_io_error() {
+        let err = BluePrint::from_file("fixtures/does_not_exist.bp").unwrap_err();
+        assert!(matches!(err, crate::Error::Io { .. }));
+        assert_eq!(err.file(), Some(std::path::Path::new("fixtures/does_not_exist.bp")));
+        assert!(std::error::Error::source(&err).is_some());
+    }
+    #[test]
+    fn test_parse_recovering() {
+        let input = r#"
+var = ["a"]
+foo {
+    name: "foo",
+    srcs: ["a.c"]
+    cflags: [],
+}
+bar {
+    name: "bar",
+}
+var += 1
+baz {
+    name: "baz" +,
+}
+qux(name = "qux")
+}
+"#;
+        let (bp, errors) = BluePrint::parse_recovering(input);
+        let names: Vec<_> = bp.modules.iter().map(|m| m.get_string("name").unwrap().as_str()).collect();
+        assert_eq!(names, ["bar", "qux"]);
+        assert_eq!(bp.variables.get("var").unwrap(), &Value::Array(vec!["a".into()]));
+        let lines: Vec<_> = errors.iter().map(|e| e.position().unwrap().line).collect();
+        assert_eq!(lines, [6, 11, 13, 16]);
+        assert!(matches!(errors[1], crate::Error::Semantic { .. }));
+        // the strict parser reports the first error
+        assert_eq!(BluePrint::parse(input).unwrap_err().position().unwrap().line, 6);
+    }
+    #[test]
+    fn test_diagnostic_hints() {
+        use crate::Diagnostic;
+        let hint = |input: &str| {
+            let err = BluePrint::parse(input).unwrap_err();
+            Diagnostic::from_error(&err, input).hint
+        };
+        let cases = [
+            ("foo {\n    name: \"a\"\n    srcs: [],\n}", "missing comma between properties"),
+            ("foo {\n    name = \"a\",\n}", "use ':' inside modules, '=' at top level"),
+            ("foo: \"a\"\n", "use ':' inside modules, '=' at top level"),
+            ("foo {\n    srcs: [\"a\" \"b\"],\n}", "missing comma between list elements"),
+            ("foo {\n    name: \"abc,\n}\n", "unterminated string"),
+            ("foo {\n    cmd: `abc,\n}\n", "unterminated string"),
+            ("foo {\n    name: \"a\\/b\",\n}\n", "invalid escape sequence, see go's strconv.Unquote"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(hint(input).as_deref(), Some(expected), "{}", input);
+        }
+        assert_eq!(hint("foo { a: 1 } }"), None);
+    }
+    #[test]
+    fn test_diagnostic_render() {
+        use crate::Diagnostic;
+        let input = "foo {\n    name: \"a\"\n    srcs: [],\n}\n";
+        let err = BluePrint::parse(input).unwrap_err().in_file(std::path::Path::new("a/Android.bp"));
+        let diagnostic = Diagnostic::from_error(&err, input);
+        assert_eq!(
+            diagnostic.render(input),
+            r#"error: syntax error, expected '}' in module
+ --> a/Android.bp:3:5
+  |
+3 |     srcs: [],
+  |     ^^^^
+  |
+  = help: missing comma between properties
+"#
+        );
+        assert_eq!(
+            crate::diagnostic::to_json(std::slice::from_ref(&diagnostic)),
+            r#"[{"severity":"error","code":"syntax","message":"syntax error, expected '}' in module","file":"a/Android.bp","span":{"start":{"offset":24,"line":3,"column":5},"end":{"offset":28,"line":3,"column":9}},"hint":"missing comma between properties"}]"#
+        );
+        let sarif = crate::diagnostic::to_sarif(&[diagnostic]);
+        assert!(sarif.contains(r#""version":"2.1.0""#));
+        assert!(sarif.contains(r#""artifactLocation":{"uri":"a/Android.bp"},"region":{"startLine":3,"startColumn":5,"endLine":3,"endColumn":9,"byteOffset":24,"byteLength":4}"#));
+    }
+    #[test]
+    fn test_diagnostic_without_location() {
+        use crate::{Diagnostic, IndexMap};
+        // modules built from values have no location
+        let entries = [("srcs".to_string(), Value::Ident("x".to_string()))].into_iter().collect();
+        let err = Module::new("foo", entries).resolved(&IndexMap::new()).unwrap_err();
+        let diagnostic = Diagnostic::from_error(&err, "");
+        assert_eq!(diagnostic.span, None);
+        assert_eq!(diagnostic.render(""), "error: variable `x` not found\n --> <input>\n");
+        assert!(!crate::diagnostic::to_sarif(&[diagnostic]).contains("region"));
+    }
 }
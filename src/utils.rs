@@ -3,17 +3,26 @@ use nom::{
     bytes::complete::{tag, take_until, take_while},
     character::complete::{alpha1, alphanumeric1, digit1, multispace1},
     combinator::{map, map_res, opt, recognize, value},
-    error::{context, VerboseError},
-    multi::{many0, many0_count, many1},
+    error::{context, VerboseError, VerboseErrorKind},
+    multi::{many0, many0_count},
     sequence::{delimited, pair, tuple},
     IResult, Parser,
 };
-use crate::string::parse_string;
+use crate::span::Span;
+use crate::string::{parse_raw_string, parse_string};
 
 /// Result type with verbose error
 pub(crate) type VerboseResult<'a, T> = IResult<&'a str, T, VerboseError<&'a str>>;
 
-pub(crate) fn comment(input: &str) -> VerboseResult<()> {
+/// fail on input which follows the grammar but is not valid, `label` telling
+/// what was expected
+pub(crate) fn invalid<'a, T>(input: &'a str, label: &'static str) -> VerboseResult<'a, T> {
+    Err(nom::Err::Failure(VerboseError {
+        errors: vec![(input, VerboseErrorKind::Context(label))],
+    }))
+}
+
+pub(crate) fn comment(input: &str) -> VerboseResult<'_, ()> {
     context(
         "comment",
         value(
@@ -23,25 +32,19 @@ pub(crate) fn comment(input: &str) -> VerboseResult<()> {
     )(input)
 }
 
-pub(crate) fn multiline_comment(input: &str) -> VerboseResult<()> {
+pub(crate) fn multiline_comment(input: &str) -> VerboseResult<'_, ()> {
     context(
         "multiline comment",
         value((), delimited(tag("/*"), take_until("*/"), tag("*/"))),
     )(input)
 }
 
-pub(crate) fn space_or_comments(input: &str) -> VerboseResult<()> {
+pub(crate) fn space_or_comments(input: &str) -> VerboseResult<'_, ()> {
     value(
         (),
         many0(alt((value((), multispace1), comment, multiline_comment))),
     )(input)
 }
-pub(crate) fn space_or_comments1(input: &str) -> VerboseResult<()> {
-    value(
-        (),
-        many1(alt((value((), multispace1), comment, multiline_comment))),
-    )(input)
-}
 
 pub(crate)fn ws<'a, F, O>(inner: F) -> impl Parser<&'a str, O, VerboseError<&'a str>>
     where
@@ -53,32 +56,42 @@ pub(crate)fn ws<'a, F, O>(inner: F) -> impl Parser<&'a str, O, VerboseError<&'a
         space_or_comments
     )
 }
-pub(crate) fn identifier(input: &str) -> VerboseResult<&str> {
+/// run a parser, and return its output along with the span of what it consumed
+pub(crate) fn spanned<'a, F, O>(mut inner: F) -> impl FnMut(&'a str) -> VerboseResult<'a, (O, Span)>
+where
+    F: Parser<&'a str, O, VerboseError<&'a str>>,
+{
+    move |input: &'a str| {
+        let (rest, output) = inner.parse(input)?;
+        Ok((rest, (output, Span::consumed(input, rest))))
+    }
+}
+pub(crate) fn identifier(input: &str) -> VerboseResult<'_, &str> {
     recognize(pair(
         alt((alpha1, tag("_"))),
         many0_count(alt((alphanumeric1, tag("_")))),
     ))(input)
 }
 
-pub(crate) fn string_literal(input: &str) -> VerboseResult<String> {
+pub(crate) fn string_literal(input: &str) -> VerboseResult<'_, String> {
     context(
         "string",
-        parse_string
+        alt((parse_string, parse_raw_string))
     )(input)
 }
 
-pub(crate) fn comma(input: &str) -> VerboseResult<&str> {
+pub(crate) fn comma(input: &str) -> VerboseResult<'_, &str> {
     ws(tag(",")).parse(input)
 }
 
-pub(crate) fn parse_bool(input: &str) -> VerboseResult<bool> {
+pub(crate) fn parse_bool(input: &str) -> VerboseResult<'_, bool> {
     alt((map(tag("true"), |_| true), map(tag("false"), |_| false)))(input)
 }
 
-pub(crate) fn parse_int(input: &str) -> VerboseResult<i64> {
+pub(crate) fn parse_int(input: &str) -> VerboseResult<'_, i64> {
     map_res(
         recognize(pair(opt(tag("-")), digit1)),
-        |x| i64::from_str_radix(x, 10),
+        |x: &str| x.parse::<i64>(),
     )(input)
 }
 #[cfg(test)]
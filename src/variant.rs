@@ -0,0 +1,383 @@
+use crate::error::Error;
+use crate::parser::{Map, Module, Value};
+
+/// the blocks of properties which only apply to some variants
+const VARIANT_BLOCKS: &[&str] = &["arch", "multilib", "target", "codegen"];
+
+/// images a device module can be installed in, besides `core`, with the
+/// properties which add them
+const OTHER_IMAGES: &[(&str, &str)] = &[
+    ("vendor", "vendor_available"),
+    ("product", "product_available"),
+    ("ramdisk", "ramdisk_available"),
+    ("vendor_ramdisk", "vendor_ramdisk_available"),
+    ("recovery", "recovery_available"),
+];
+
+/// module types which are not arch specific: soong builds them once, for no
+/// os nor arch
+const NOT_ARCH_SPECIFIC: &[&str] = &[
+    "filegroup",
+    "license",
+    "license_kind",
+    "package",
+    "soong_namespace",
+    "soong_config_module_type",
+    "soong_config_module_type_import",
+    "soong_config_bool_variable",
+    "soong_config_string_variable",
+    "apex_key",
+    "android_app_certificate",
+];
+
+/// a variant of a module: the os, the arch, the multilib and the image it is built for
+#[derive(Debug, PartialEq, Clone, Eq, Hash)]
+pub struct Variant {
+    /// `android`, `linux_glibc`, `linux_musl`, `linux_bionic`, `darwin` or `windows`,
+    /// empty for modules which are not arch specific, like `filegroup`
+    pub os: String,
+    /// `arm`, `arm64`, `x86`, `x86_64` or `riscv64`, or `common` for modules
+    /// built once per os, like java modules
+    pub arch: String,
+    /// `lib32`, `lib64` or `common`
+    pub multilib: String,
+    /// `core`, `vendor`, `product`, `ramdisk`, `vendor_ramdisk` or `recovery`
+    pub image: String,
+}
+
+impl Variant {
+    /// a variant in the `core` image
+    pub fn new(os: &str, arch: &str, multilib: &str) -> Self {
+        Variant {
+            os: os.to_string(),
+            arch: arch.to_string(),
+            multilib: multilib.to_string(),
+            image: "core".to_string(),
+        }
+    }
+    /// the same variant, in another image
+    pub fn with_image(&self, image: &str) -> Self {
+        Variant {
+            image: image.to_string(),
+            ..self.clone()
+        }
+    }
+    /// the variant is built for the host, not for the device
+    pub fn is_host(&self) -> bool {
+        !self.os.is_empty() && self.os != "android"
+    }
+    fn is_linux(&self) -> bool {
+        matches!(
+            self.os.as_str(),
+            "android" | "linux_glibc" | "linux_musl" | "linux_bionic"
+        )
+    }
+    fn is_bionic(&self) -> bool {
+        matches!(self.os.as_str(), "android" | "linux_bionic")
+    }
+    /// the `property.name` blocks which apply to the variant, in the order soong
+    /// appends them to the properties of the module
+    fn blocks(&self) -> Vec<(&'static str, String)> {
+        let (os, arch) = (self.os.as_str(), self.arch.as_str());
+        let mut targets = Vec::new();
+        if self.is_host() {
+            targets.push("host".to_string());
+            if self.is_linux() {
+                targets.push("host_linux".to_string());
+            }
+        }
+        if self.is_linux() {
+            targets.push("linux".to_string());
+            targets.push(format!("linux_{}", arch));
+        }
+        if self.is_bionic() {
+            targets.push("bionic".to_string());
+            targets.push(format!("bionic_{}", arch));
+        }
+        match os {
+            "linux_glibc" => targets.push("glibc".to_string()),
+            "linux_musl" => targets.push("musl".to_string()),
+            _ => {}
+        }
+        targets.push(os.to_string());
+        targets.push(format!("{}_{}", os, arch));
+        // like soong, only for host variants
+        if self.is_host() && os != "windows" {
+            targets.push("not_windows".to_string());
+        }
+        match (os, self.multilib.as_str()) {
+            ("android", "lib32") => targets.push("android32".to_string()),
+            ("android", "lib64") => targets.push("android64".to_string()),
+            _ => {}
+        }
+        if self.image != "core" {
+            targets.push(self.image.clone());
+        }
+        let mut blocks = vec![("arch", arch.to_string()), ("multilib", self.multilib.clone())];
+        blocks.extend(targets.into_iter().map(|t| ("target", t)));
+        blocks.push(("codegen", arch.to_string()));
+        blocks
+    }
+}
+
+/// append the value of a property of a block to the value of a module, as soong
+/// appends the properties of variant specific blocks: lists are appended, maps
+/// are merged, and other values are replaced.
+fn append(value: Value, extra: &Value) -> Result<Value, String> {
+    Ok(match (value, extra) {
+        (Value::Map(mut m), Value::Map(e)) => {
+            m.merge_with(e, append)?;
+            Value::Map(m)
+        }
+        (v, e) if matches!(v, Value::Array(_)) || matches!(e, Value::Array(_)) => v.plus(e.clone())?,
+        (_, e) => e.clone(),
+    })
+}
+
+/// append the properties of the block `name` to the properties of the module,
+/// see [`append`]. Type mismatches are reported at the property of the block.
+pub(crate) fn append_properties(module: &mut Module, block: &Map, name: &str) -> Result<(), Error> {
+    for (k, extra) in block.iter() {
+        let value = match module.entries.get(k) {
+            Some(v) => append(v.clone(), extra).map_err(|e| {
+                let span = block.value_span(k).unwrap_or(block.span());
+                Error::semantic(span, format!("cannot apply {} to property \"{}\": {}", name, k, e))
+            })?,
+            None => {
+                if let Some(span) = block.1.entries.get(k) {
+                    module.spans.entries.insert(k.clone(), *span);
+                }
+                extra.clone()
+            }
+        };
+        module.set(k, value);
+    }
+    Ok(())
+}
+
+impl Module {
+    /// the module as built for a variant: the `arch`, `multilib`, `target` and
+    /// `codegen` blocks which match the variant are merged into the properties,
+    /// in soong's order, and the others are dropped.
+    ///
+    /// Lists of the blocks are appended, maps merged, and other values replaced.
+    /// The module should be evaluated first, see [`crate::BluePrint::evaluate`].
+    pub fn for_variant(&self, variant: &Variant) -> Result<Module, Error> {
+        let mut module = self.clone();
+        for key in VARIANT_BLOCKS {
+            module.remove(key);
+        }
+        for (property, name) in variant.blocks() {
+            let Some(Value::Map(blocks)) = self.get(property) else {
+                continue;
+            };
+            let full_name = format!("{}.{}", property, name);
+            match blocks.get(&name) {
+                Some(Value::Map(block)) => append_properties(&mut module, block, &full_name)?,
+                Some(_) => {
+                    let span = blocks.value_span(&name).unwrap_or(blocks.span());
+                    return Err(Error::semantic(span, format!("{} must be a map", full_name)));
+                }
+                None => {}
+            }
+        }
+        Ok(module)
+    }
+}
+
+/// the targets of one os class which a module builds for, given its `compile_multilib`.
+/// `common` is a single target of the `common` arch.
+fn select_multilib(targets: &[&Variant], compile_multilib: &str) -> Vec<Variant> {
+    let lib32: Vec<_> = targets.iter().copied().filter(|t| t.multilib == "lib32").collect();
+    let lib64: Vec<_> = targets.iter().copied().filter(|t| t.multilib == "lib64").collect();
+    let selected = match compile_multilib {
+        "common" => {
+            return vec![Variant {
+                arch: "common".to_string(),
+                multilib: "common".to_string(),
+                ..targets[0].clone()
+            }];
+        }
+        "both" => targets.to_vec(),
+        "32" => lib32,
+        "64" => lib64,
+        "prefer32" if !lib32.is_empty() => lib32[..1].to_vec(),
+        _ => targets.iter().take(1).copied().collect(),
+    };
+    selected.into_iter().cloned().collect()
+}
+
+impl Module {
+    /// the module does not build for the device by default, like `cc_binary_host`
+    fn is_host_module(&self) -> bool {
+        self.typ.ends_with("_host") || self.typ.contains("_host_")
+    }
+    /// the `compile_multilib` of the module type, as soong sets it: `common` for
+    /// java modules, which do not depend on the arch, `first` for binaries and
+    /// for prebuilt files like `prebuilt_etc`, and `both` for other modules
+    fn default_multilib(&self) -> &'static str {
+        let typ = self.typ.as_str();
+        if typ.starts_with("java_") || typ.starts_with("android_") || matches!(typ, "genrule" | "gensrcs") {
+            "common"
+        } else if typ.contains("binary") || typ.starts_with("prebuilt_") {
+            "first"
+        } else {
+            "both"
+        }
+    }
+    /// the images the module is installed in on the device, `core` first
+    fn images(&self) -> Vec<&'static str> {
+        let is_set = |key: &str| self.get_bool(key) == Some(true);
+        let only = [
+            ("vendor", "vendor"),
+            ("vendor", "soc_specific"),
+            ("vendor", "proprietary"),
+            ("vendor", "device_specific"),
+            ("product", "product_specific"),
+            ("ramdisk", "ramdisk"),
+            ("vendor_ramdisk", "vendor_ramdisk"),
+            ("recovery", "recovery"),
+        ];
+        if let Some((image, _)) = only.iter().find(|(_, key)| is_set(key)) {
+            return vec![image];
+        }
+        let mut images = vec!["core"];
+        images.extend(
+            OTHER_IMAGES
+                .iter()
+                .filter(|(_, key)| is_set(key))
+                .map(|(image, _)| *image),
+        );
+        images
+    }
+    /// the variants soong builds for the module, given the `targets` of the
+    /// build, like `android` `arm64` `lib64`, `android` `arm` `lib32` and
+    /// `linux_glibc` `x86_64` `lib64`, first target of each os first.
+    ///
+    /// It follows `host_supported`, `device_supported`, `compile_multilib`,
+    /// `enabled` in the variant specific blocks, and the properties installing
+    /// the module in other images, like `vendor_available`. Ramdisk and
+    /// recovery variants only use the first device target.
+    ///
+    /// `compile_multilib` defaults to the one of the module type: java modules
+    /// and genrules are built once per os, for the `common` arch, binaries and
+    /// `prebuilt_etc` for the first target, and other modules, like libraries,
+    /// for both. Modules which are not arch specific, like `filegroup`, have a
+    /// single variant, with an empty os and arch.
+    /// The module should be evaluated, with its defaults applied, see
+    /// [`Module::with_defaults`].
+    pub fn variants(&self, targets: &[Variant]) -> Result<Vec<Variant>, Error> {
+        if self.typ.ends_with("defaults") {
+            return Ok(Vec::new());
+        }
+        if NOT_ARCH_SPECIFIC.contains(&self.typ.as_str()) {
+            if self.get_bool("enabled") == Some(false) {
+                return Ok(Vec::new());
+            }
+            return Ok(vec![Variant::new("", "", "")]);
+        }
+        let host_module = self.is_host_module();
+        let device_supported = !host_module && self.get_bool("device_supported") != Some(false);
+        let host_supported = host_module || self.get_bool("host_supported") == Some(true);
+        let images = self.images();
+        let mut variants = Vec::new();
+        let mut oses: Vec<&str> = Vec::new();
+        for target in targets {
+            if !oses.contains(&target.os.as_str()) {
+                oses.push(&target.os);
+            }
+        }
+        for os in oses {
+            let os_targets: Vec<&Variant> = targets.iter().filter(|t| t.os == os).collect();
+            let supported = if os == "android" { device_supported } else { host_supported };
+            if !supported {
+                continue;
+            }
+            // compile_multilib can be set in target blocks, like target.host
+            let first = os_targets[0].with_image("core");
+            let compile_multilib = match self.default_multilib() {
+                // the property of java modules is about their jni libraries
+                "common" => "common".to_string(),
+                default => self
+                    .for_variant(&first)?
+                    .get_string("compile_multilib")
+                    .cloned()
+                    .unwrap_or_else(|| default.to_string()),
+            };
+            for (i, target) in select_multilib(&os_targets, &compile_multilib).into_iter().enumerate() {
+                let target_images = if os == "android" { images.as_slice() } else { &["core"] };
+                for image in target_images {
+                    let first_only = matches!(*image, "ramdisk" | "vendor_ramdisk" | "recovery");
+                    if first_only && i > 0 {
+                        continue;
+                    }
+                    let variant = target.with_image(image);
+                    if self.for_variant(&variant)?.get_bool("enabled") != Some(false) {
+                        variants.push(variant);
+                    }
+                }
+            }
+        }
+        Ok(variants)
+    }
+}
+
+/// the linkage variant of a `cc_library`
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash)]
+pub enum Linkage {
+    Static,
+    Shared,
+}
+
+impl Linkage {
+    /// the property holding the properties specific to the linkage
+    fn key(self) -> &'static str {
+        match self {
+            Linkage::Static => "static",
+            Linkage::Shared => "shared",
+        }
+    }
+}
+
+impl Module {
+    /// the module as built for a linkage: the properties of the `static` or the
+    /// `shared` block are appended to the properties of the module, including
+    /// the blocks nested in variant specific blocks, like `target.android.static`.
+    /// The blocks of the other linkage are dropped.
+    pub fn for_linkage(&self, linkage: Linkage) -> Result<Module, Error> {
+        let mut module = self.clone();
+        for key in ["static", "shared"] {
+            module.remove(key);
+        }
+        for property in VARIANT_BLOCKS {
+            let Some(Value::Map(mut blocks)) = module.get(property).cloned() else {
+                continue;
+            };
+            for (name, block) in blocks.iter_mut() {
+                let Value::Map(block) = block else {
+                    continue;
+                };
+                let span = block.value_span(linkage.key()).unwrap_or(block.span());
+                let nested = block.shift_remove(linkage.key());
+                block.shift_remove("static");
+                block.shift_remove("shared");
+                if let Some(Value::Map(nested)) = nested {
+                    block.merge_with(&nested, append).map_err(|e| {
+                        let message = format!("cannot apply {}.{}.{}: {}", property, name, linkage.key(), e);
+                        Error::semantic(span, message)
+                    })?;
+                }
+            }
+            module.set(property, Value::Map(blocks));
+        }
+        match self.get(linkage.key()) {
+            Some(Value::Map(block)) => append_properties(&mut module, block, linkage.key())?,
+            Some(_) => {
+                let span = self.value_span(linkage.key()).unwrap_or(self.span());
+                return Err(Error::semantic(span, format!("{} must be a map", linkage.key())));
+            }
+            None => {}
+        }
+        Ok(module)
+    }
+}